@@ -1,24 +1,39 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
 use qubicon_input_server::{keymaps::{Abs, Key}, ActionEventType, ActionInputEntry, LinuxInputServer};
 use qubicon_vulkan::{descriptors::{alloc::DescriptorPoolSize, DescriptorBinding, DescriptorPool, DescriptorPoolCreateInfo, DescriptorSetLayout, DescriptorSetLayoutCreateInfo, DescriptorType}, device::{create_info::{DeviceCreateInfo, QueueFamilyUsage}, Device}, instance::{creation_info::InstanceCreateInfo, physical_device::{queue_info::QueueFamilyCapabilities, PhysicalDevice}}, memory::{alloc::standart_device_memory_allocator::StandartMemoryAllocator, resources::{buffer::Buffer, format::Format, image::{Image, ImageUsageFlags}}, ResourceFactory}, queue::Queue, shaders::{compute::{ComputePipeline, ComputePipelineCreateInfo}, pipeline_layout::PipelineLayout, PipelineShaderStageCreateInfo, ShaderStageFlags}, surface::{CompositeAlphaFlags, PresentMode, SurfaceTransformFlags}, Instance};
 use qubicon_windowing::{x11::{WindowId, WindowingServer}, AssociatedSwapchainCreateInfo};
 
+use self::{config::{Config, Extent, PresentModePreference}, spirv::reflect::{DescriptorKind, ExpectedBinding, ShaderReflection}};
+
 const SHADER_SRC: &[u8] = include_bytes!("shader/rendering_shader.spv");
 
+// render target is blitted to the swapchain, so any format works. with UNORM ones
+// the shader does sRGB encoding itself, so they behave the same on every driver
+const SURFACE_FORMATS: [Format; 2] = [Format::B8G8R8A8_UNORM, Format::R8G8B8A8_UNORM];
+
+pub mod config;
 mod run;
+mod spirv;
+
+pub use self::run::{inspect_octree, load_world, render_cpu_reference, CpuReferenceError, World};
 
 pub struct Application {
+    config: Config,
+
     vk_ctx: VulkanContext,
     input_server: LinuxInputServer,
     windowing_server: WindowingServer,
+    // actions which were pressed on the last input update
+    held_actions: HashSet<&'static str>,
 
     descriptor_pool: DescriptorPool,
     descriptor_set_layout: Arc<DescriptorSetLayout>,
     pipeline_layout: Arc<PipelineLayout>,
 
     window_id: WindowId,
-    rendering_pipeline: Arc<ComputePipeline>
+    rendering_pipeline: Arc<ComputePipeline>,
+    post_pipeline: Arc<ComputePipeline>
 }
 
 
@@ -123,80 +138,200 @@ impl Application {
         );
 
 
+        // sun controls
+        input_server.add_input_action(
+            "sun_left",
+            [
+                ActionInputEntry {
+                    device_id: None,
+                    r#type: ActionEventType::Key { key: Key::J, pressed: true }
+                }
+            ]
+        );
+        input_server.add_input_action(
+            "sun_right",
+            [
+                ActionInputEntry {
+                    device_id: None,
+                    r#type: ActionEventType::Key { key: Key::L, pressed: true }
+                }
+            ]
+        );
+        input_server.add_input_action(
+            "sun_up",
+            [
+                ActionInputEntry {
+                    device_id: None,
+                    r#type: ActionEventType::Key { key: Key::I, pressed: true }
+                }
+            ]
+        );
+        input_server.add_input_action(
+            "sun_down",
+            [
+                ActionInputEntry {
+                    device_id: None,
+                    r#type: ActionEventType::Key { key: Key::K, pressed: true }
+                }
+            ]
+        );
+
+
+        // exposure controls
+        input_server.add_input_action(
+            "exposure_down",
+            [
+                ActionInputEntry {
+                    device_id: None,
+                    r#type: ActionEventType::Key { key: Key::U, pressed: true }
+                }
+            ]
+        );
+        input_server.add_input_action(
+            "exposure_up",
+            [
+                ActionInputEntry {
+                    device_id: None,
+                    r#type: ActionEventType::Key { key: Key::O, pressed: true }
+                }
+            ]
+        );
+
+
+        // toggles
+        input_server.add_input_action(
+            "cycle_present_mode",
+            [
+                ActionInputEntry {
+                    device_id: None,
+                    r#type: ActionEventType::Key { key: Key::V, pressed: true }
+                }
+            ]
+        );
+        input_server.add_input_action(
+            "cycle_view_mode",
+            [
+                ActionInputEntry {
+                    device_id: None,
+                    r#type: ActionEventType::Key { key: Key::M, pressed: true }
+                }
+            ]
+        );
+        input_server.add_input_action(
+            "cycle_tonemapper",
+            [
+                ActionInputEntry {
+                    device_id: None,
+                    r#type: ActionEventType::Key { key: Key::T, pressed: true }
+                }
+            ]
+        );
+
+        // editing
+        input_server.add_input_action(
+            "pick_voxel",
+            [
+                ActionInputEntry {
+                    device_id: None,
+                    r#type: ActionEventType::Key { key: Key::P, pressed: true }
+                }
+            ]
+        );
+        input_server.add_input_action(
+            "paint_voxel",
+            [
+                ActionInputEntry {
+                    device_id: None,
+                    r#type: ActionEventType::Key { key: Key::C, pressed: true }
+                }
+            ]
+        );
+
+
         input_server
     }
 
+    // FIFO is the only mode every driver must support, so it ends every chain
+    fn present_mode_fallbacks(preference: PresentModePreference) -> &'static [PresentMode] {
+        match preference {
+            PresentModePreference::VSync => &[PresentMode::Fifo],
+            PresentModePreference::Mailbox => &[PresentMode::Mailbox, PresentMode::Fifo],
+            PresentModePreference::Off => &[PresentMode::Immediate, PresentMode::Mailbox, PresentMode::Fifo]
+        }
+    }
+
     // inits window server and creates window with swapchain
-    fn init_windowing_server(vk_ctx: &VulkanContext, width: u32, height: u32) -> (WindowId, WindowingServer) {
+    fn init_windowing_server(vk_ctx: &VulkanContext, size: Extent, present_mode: PresentModePreference) -> (WindowId, WindowingServer) {
         let mut windowing_server = WindowingServer::init();
 
-        let window_id = windowing_server.create_window_vulkan(
-            &vk_ctx.device,
-            width,
-            height,
-            &AssociatedSwapchainCreateInfo {
-                min_image_count: 3,
-                image_array_layers: 1,
-                image_usage: ImageUsageFlags::TRANSFER_DST | /* tmp */ ImageUsageFlags::STORAGE,
-
-                pre_transform: SurfaceTransformFlags::IDENTITY,
-                composite_alpha: CompositeAlphaFlags::OPAQUE,
-               
-                clipped: false,
-                image_main_owner_queue_family: vk_ctx.queue_family
-            },
-            | mode | mode == PresentMode::Immediate /* V-Sync */,
-            // dont care
-            | f | true
-        ).expect("failed to create window");
+        // window creation only takes predicates, so preferences are tried one by one.
+        // any format is better than no window at all, so it is the last resort
+        let attempts = Self::present_mode_fallbacks(present_mode).iter()
+            .flat_map(| &mode | SURFACE_FORMATS.map(Some).into_iter().chain([None]).map(move | format | (mode, format)));
+
+        let window_id = attempts
+            .filter_map(| (mode, format) | windowing_server.create_window_vulkan(
+                &vk_ctx.device,
+                size.width,
+                size.height,
+                &AssociatedSwapchainCreateInfo {
+                    min_image_count: 3,
+                    image_array_layers: 1,
+                    image_usage: ImageUsageFlags::TRANSFER_DST,
+
+                    pre_transform: SurfaceTransformFlags::IDENTITY,
+                    composite_alpha: CompositeAlphaFlags::OPAQUE,
+
+                    clipped: false,
+                    image_main_owner_queue_family: vk_ctx.queue_family
+                },
+                | m | m == mode,
+                | f | format.is_none_or(| format | f.format == format)
+            ).ok())
+            .next()
+            .expect("failed to create window");
 
         (window_id, windowing_server)
     }
 
-    fn create_vulkan_objects(vk_ctx: &VulkanContext, buffered_frames_count: u32) -> (Arc<DescriptorSetLayout>, Arc<PipelineLayout>, Arc<ComputePipeline>, DescriptorPool) {
-        let descriptor_set_layout = unsafe {
-            vk_ctx.device.create_descriptor_set_layout_unchecked(
-                DescriptorSetLayoutCreateInfo {
-                    bindings: [
-                        DescriptorBinding {
-                            shader_stage_flags: ShaderStageFlags::COMPUTE,
-                            r#type: DescriptorType::UniformBuffer,
-                            count: 1
-                        },
-                        DescriptorBinding {
-                            shader_stage_flags: ShaderStageFlags::COMPUTE,
-                            r#type: DescriptorType::StorageBuffer,
-                            count: 1
-                        },
-                        DescriptorBinding {
-                            shader_stage_flags: ShaderStageFlags::COMPUTE,
-                            r#type: DescriptorType::StorageImage,
-                            count: 1
-                        }
-                    ]
-                }
-            )
-        }.expect("failed to create descriptor set layout");
+    // swapchain images in these formats are encoded by hardware on write
+    fn is_srgb_format(format: Format) -> bool {
+        matches!(format, Format::B8G8R8A8_SRGB | Format::R8G8B8A8_SRGB | Format::A8B8G8R8_SRGB_PACK32)
+    }
 
-        let pipeline_layout = vk_ctx.device.create_pipeline_layout(
-            [Arc::clone(&descriptor_set_layout)]
-        ).expect("failed to create pipeline layout");
+    // every shader should go through this, driver will happily crash on a broken binary
+    // or a descriptor layout which does not match the shader
+    fn load_shader(name: &str, src: &[u8], set_layout: &[ExpectedBinding]) -> Vec<u32> {
+        let binary = spirv::load(src)
+            .unwrap_or_else(| e | panic!("invalid SPIR-V in {name}: {e}"));
+
+        let reflection = ShaderReflection::reflect(&binary)
+            .unwrap_or_else(| e | panic!("failed to reflect {name}: {e}"));
+
+        if let Err(mismatches) = reflection.check_set(0, set_layout) {
+            let mismatches: Vec<_> = mismatches.iter()
+                .map(ToString::to_string)
+                .collect();
+
+            panic!("descriptor set layout does not match {name}:\n  {}", mismatches.join("\n  "));
+        }
+
+        binary
+    }
 
-        let rendering_pipeline = unsafe {
-            let shader_module = {
-                let mut binary = Vec::<u32>::with_capacity(SHADER_SRC.len() / 4);
-                
-                // :)
-                core::ptr::copy_nonoverlapping(
-                    SHADER_SRC.as_ptr(),
-                    binary.as_mut_ptr().cast(),
-                    SHADER_SRC.len()
-                );
-                binary.set_len(SHADER_SRC.len() / 4);
+    // one pipeline per pass, both from the same shader
+    fn create_pipeline(vk_ctx: &VulkanContext, config: &Config, pipeline_layout: &Arc<PipelineLayout>, pass: u32) -> Arc<ComputePipeline> {
+        unsafe {
+            let mut binary = Self::load_shader("rendering_shader", SHADER_SRC, &run::RENDER_SET_LAYOUT);
 
+            spirv::specialize(&mut binary, run::TILE_WIDTH_SPEC_ID, config.tile_size.width)
+                .and_then(| _ | spirv::specialize(&mut binary, run::TILE_HEIGHT_SPEC_ID, config.tile_size.height))
+                .and_then(| _ | spirv::specialize(&mut binary, run::PASS_SPEC_ID, pass))
+                .and_then(| _ | spirv::specialize(&mut binary, run::NODE_LAYOUT_SPEC_ID, config.node_layout as u32))
+                .expect("failed to specialize rendering_shader");
 
-                vk_ctx.device.create_shader_module_from_binary(&binary)
-            }.expect("failed to create shader module");
+            let shader_module = vk_ctx.device.create_shader_module_from_binary(&binary)
+                .expect("failed to create shader module");
 
             vk_ctx.device.create_compute_pipeline_unchecked(
                 ComputePipelineCreateInfo {
@@ -206,57 +341,86 @@ impl Application {
                         module: &shader_module,
                         entry_name: "main"
                     },
-                    layout: Arc::clone(&pipeline_layout),
+                    layout: Arc::clone(pipeline_layout),
                     base_pipeline: None
                 }
             )
-        }.expect("failed to create rendering pipeline");
+        }.expect("failed to create compute pipeline")
+    }
+
+    fn create_vulkan_objects(vk_ctx: &VulkanContext, config: &Config, buffered_frames_count: u32) -> (Arc<DescriptorSetLayout>, Arc<PipelineLayout>, [Arc<ComputePipeline>; 2], DescriptorPool) {
+        let descriptor_set_layout = unsafe {
+            vk_ctx.device.create_descriptor_set_layout_unchecked(
+                DescriptorSetLayoutCreateInfo {
+                    bindings: run::RENDER_SET_LAYOUT.map(| binding | DescriptorBinding {
+                        shader_stage_flags: ShaderStageFlags::COMPUTE,
+                        r#type: binding.kind.into(),
+                        count: 1
+                    })
+                }
+            )
+        }.expect("failed to create descriptor set layout");
+
+        let pipeline_layout = vk_ctx.device.create_pipeline_layout(
+            [Arc::clone(&descriptor_set_layout)]
+        ).expect("failed to create pipeline layout");
+
+        let pipelines = [run::PASS_RENDER, run::PASS_POST]
+            .map(| pass | Self::create_pipeline(vk_ctx, config, &pipeline_layout, pass));
 
 
         
         let descriptor_pool = vk_ctx.device.create_descriptor_pool(
             DescriptorPoolCreateInfo {
                 max_sets: buffered_frames_count,
-                pool_sizes: [
-                    DescriptorPoolSize {
-                        r#type: DescriptorType::UniformBuffer,
-                        count: buffered_frames_count
-                    },
-                    DescriptorPoolSize {
-                        r#type: DescriptorType::StorageBuffer,
-                        count: buffered_frames_count
-                    },
-                    DescriptorPoolSize {
-                        r#type: DescriptorType::StorageImage,
-                        count: buffered_frames_count
-                    }
-                ]
+                pool_sizes: run::RENDER_SET_LAYOUT.map(| binding | DescriptorPoolSize {
+                    r#type: binding.kind.into(),
+                    count: buffered_frames_count
+                })
             }
         ).expect("failed to create descriptor pool");
 
 
-        return (descriptor_set_layout, pipeline_layout, rendering_pipeline, descriptor_pool);
+        return (descriptor_set_layout, pipeline_layout, pipelines, descriptor_pool);
     }
     
-    pub fn init() -> Self {
+    pub fn init(config: Config) -> Self {
         let vk_ctx = VulkanContext::init();
 
         let input_server = Self::init_input_server();
-        let (window_id, windowing_server) = Self::init_windowing_server(&vk_ctx, 600, 400);
+        let (window_id, windowing_server) = Self::init_windowing_server(&vk_ctx, config.window_size, config.present_mode);
 
-        let (descriptor_set_layout, pipeline_layout, rendering_pipeline, descriptor_pool) = Self::create_vulkan_objects(&vk_ctx, 1);
+        let (descriptor_set_layout, pipeline_layout, [rendering_pipeline, post_pipeline], descriptor_pool) = Self::create_vulkan_objects(&vk_ctx, &config, 1);
         
         Self {
+            config,
+
             vk_ctx,
             input_server,
             windowing_server,
+            held_actions: HashSet::new(),
 
             descriptor_pool,
             descriptor_set_layout,
             pipeline_layout,
 
             window_id,
-            rendering_pipeline
+            rendering_pipeline,
+            post_pipeline
+        }
+    }
+}
+
+
+impl From<DescriptorKind> for DescriptorType {
+    fn from(value: DescriptorKind) -> Self {
+        match value {
+            DescriptorKind::Sampler => Self::Sampler,
+            DescriptorKind::CombinedImageSampler => Self::CombinedImageSampler,
+            DescriptorKind::SampledImage => Self::SampledImage,
+            DescriptorKind::StorageImage => Self::StorageImage,
+            DescriptorKind::UniformBuffer => Self::UniformBuffer,
+            DescriptorKind::StorageBuffer => Self::StorageBuffer
         }
     }
 }
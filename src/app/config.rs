@@ -0,0 +1,989 @@
+//! Command line configuration.
+
+use std::{fmt, path::PathBuf, str::FromStr};
+
+pub const USAGE: &str = "\
+usage: middle_school_final_project [inspect] [options]
+
+commands:
+    inspect                               print statistics of the octree, check it for errors and exit
+
+options:
+    --size <WIDTHxHEIGHT>                 initial window size [default: 600x400]
+    --render-scale <SCALE>                render resolution relative to the window, 0.25 - 4.0 [default: 1.0]
+    --present-mode <vsync|mailbox|off>    swapchain present mode, falls back to vsync if unsupported [default: vsync]
+    --view <MODE>                         shaded, depth, normal, octree-depth, steps, node-hash, hit-mask
+                                          or path-traced [default: shaded]
+    --sun <AZIMUTH,ELEVATION>             sun direction in degrees [default: 30,50]
+    --sun-color <R,G,B>                   linear sun colour, may go above one [default: 1,0.95,0.85]
+    --ambient <R,G,B>                     linear ambient light colour [default: 0.12,0.14,0.18]
+    --aa <none|2x2|rgss>                  supersampling pattern, 2x2 grid or 4 rotated grid samples [default: none]
+    --jitter                              move samples every frame and accumulate while the view is still
+    --exposure <STOPS>                    brightness before tonemapping, in stops [default: 0]
+    --tonemapper <none|reinhard|aces>     HDR to display mapping of the lit views [default: aces]
+    --fog <DENSITY>                       exponential fog density per unit of distance, 0 disables it [default: 0.04]
+    --ao-samples <COUNT>                  ambient occlusion rays per pixel, 0 - 64, 0 disables it [default: 8]
+    --ao-radius <DISTANCE>                ambient occlusion ray length, the root node is 2 units wide [default: 0.5]
+    --bounces <COUNT>                     path tracer bounces after the first hit, 0 - 16 [default: 4]
+    --scene <fractal|terrain>             what to build the octree from [default: fractal]
+    --terrain <KEY=VALUE,...>             terrain generator parameters, any of seed, depth (1 - 9), octaves, frequency,
+                                          sea-level (0 - 1), caves (tunnel width, 0 disables them) and dirt (layer
+                                          thickness in voxels) [default: seed=1,depth=6,octaves=5,frequency=2,
+                                          sea-level=0.35,caves=0,dirt=3]
+    --heightmap <FILE>                    build the octree from a grayscale PGM or PNG heightmap, 8 or 16 bit, with one
+                                          voxel column per pixel instead of the --scene
+    --color-map <FILE>                    PPM or PNG image as large as the heightmap to colour its columns with
+    --height-scale <SCALE>                column height of a white heightmap pixel relative to the map width [default: 0.25]
+    --mesh <FILE>                         voxelize an OBJ (Y up) or STL (Z up) mesh instead of the --scene, colours come
+                                          from the OBJ materials or from vertex or STL facet colours
+    --mesh-depth <DEPTH>                  the longest side of the mesh is 2^DEPTH voxels, 1 - 10 [default: 7]
+    --fill <surface|parity|winding>       voxelize only the surface or the inside too, telling inside from outside by
+                                          ray crossing parity or winding number. filling needs a closed mesh [default: surface]
+    --points <FILE>                       import a PLY or XYZ point cloud, Z up and optionally with RGB, instead of the --scene
+    --point-depth <DEPTH>                 the longest side of the point cloud is 2^DEPTH voxels, 1 - 12 [default: 10]
+    --volume <PATH>                       import a scalar volume instead of the --scene, a .raw file or a directory of
+                                          PNG or PGM slices numbered from the bottom up
+    --volume-size <WxHxD>                 samples along each axis of a .raw volume, x varies fastest and z slowest
+    --volume-bits <8|16>                  bits per sample of a .raw volume, 16 bit samples are little endian [default: 8]
+    --transfer <THRESHOLD|STOPS>          volume samples at or above the threshold are solid, or comma separated
+                                          VALUE:#RRGGBB stops colour the samples from their value up to the next stop.
+                                          values are 0 - 1 of the sample range [default: 0.5]
+    --colors <COUNT>                      palette entries imported colours are reduced to, 1 - 256 [default: 16]
+    --lod <PIXELS>                        stop descending at nodes smaller than this on screen, 0 disables it [default: 1]
+    --node-layout <full|compact>          octree encoding on the GPU, compact needs about a tenth of the memory [default: full]
+    --cpu-reference <FILE>                render the first frame on the CPU into a PPM image and exit
+    --tile-size <WIDTHxHEIGHT>            compute workgroup size, at most 1024 invocations [default: 8x8]
+    --benchmark <SECONDS>                 render for SECONDS, print frame time statistics and exit.
+                                          use with `--present-mode off`, vsync caps the frame rate
+    -h, --help                            print this message
+";
+
+pub const RENDER_SCALE_RANGE: core::ops::RangeInclusive<f32> = 0.25..=4.0;
+/// Upper limit of most desktop GPUs, driver will reject larger workgroups anyway
+pub const MAX_TILE_INVOCATIONS: u32 = 1024;
+pub const MAX_AO_SAMPLES: u32 = 64;
+pub const MAX_PALETTE_COLORS: u32 = 256;
+pub const MAX_MESH_DEPTH: u32 = 10;
+// as deep as the walks go
+pub const MAX_POINT_DEPTH: u32 = 12;
+pub const MAX_TRANSFER_STOPS: usize = MAX_PALETTE_COLORS as usize;
+pub const MAX_BOUNCES: u32 = 16;
+/// Generating a terrain is cubic in its width, so past this it takes minutes
+pub const MAX_TERRAIN_DEPTH: u32 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    pub width: u32,
+    pub height: u32
+}
+
+impl Extent {
+    /// Scaled extent, never smaller than a single pixel
+    pub fn scaled(self, scale: f32) -> Self {
+        Self {
+            width: ((self.width as f32 * scale).round() as u32).max(1),
+            height: ((self.height as f32 * scale).round() as u32).max(1)
+        }
+    }
+}
+
+impl FromStr for Extent {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s.split_once('x').ok_or(())?;
+        let extent = Self {
+            width: width.parse().map_err(| _ | ())?,
+            height: height.parse().map_err(| _ | ())?
+        };
+
+        if extent.width == 0 || extent.height == 0 {
+            return Err(());
+        }
+
+        Ok(extent)
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PresentModePreference {
+    /// FIFO, never tears, caps frame rate at refresh rate
+    #[default]
+    VSync,
+    /// Tear free, but does not block on present. Falls back to `VSync`
+    Mailbox,
+    /// Immediate, tears. Falls back to `Mailbox`, then `VSync`
+    Off
+}
+
+impl PresentModePreference {
+    /// Next mode for the runtime toggle
+    pub fn next(self) -> Self {
+        match self {
+            Self::VSync => Self::Mailbox,
+            Self::Mailbox => Self::Off,
+            Self::Off => Self::VSync
+        }
+    }
+}
+
+impl FromStr for PresentModePreference {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vsync" => Ok(Self::VSync),
+            "mailbox" => Ok(Self::Mailbox),
+            "off" => Ok(Self::Off),
+
+            _ => Err(())
+        }
+    }
+}
+
+impl fmt::Display for PresentModePreference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::VSync => "vsync",
+            Self::Mailbox => "mailbox",
+            Self::Off => "off"
+        })
+    }
+}
+
+
+/// Comma separated list of exactly `N` numbers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Floats<const N: usize>(pub [f32; N]);
+
+impl<const N: usize> FromStr for Floats<N> {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut values = [0.0f32; N];
+        let mut parts = s.split(',');
+
+        for value in &mut values {
+            *value = parts.next().ok_or(())?.trim().parse().map_err(| _ | ())?;
+        }
+
+        if parts.next().is_some() || values.iter().any(| v | !v.is_finite()) {
+            return Err(());
+        }
+
+        Ok(Self(values))
+    }
+}
+
+
+/// What the rendering shader outputs. Discriminants must match VIEW_* in rendering_shader.comp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ViewMode {
+    #[default]
+    Shaded = 0,
+    /// Distance to the hit, brighter is closer
+    Depth = 1,
+    /// Hit face normal mapped to [0, 1]
+    Normal = 2,
+    /// Deepest octree layer the ray reached, as heatmap
+    OctreeDepth = 3,
+    /// Number of box tests per pixel, as heatmap
+    Steps = 4,
+    /// Colour derived from the index of the hit node
+    NodeHash = 5,
+    HitMask = 6,
+    /// Progressive, accumulates while nothing changes
+    PathTraced = 7
+}
+
+impl ViewMode {
+    /// Indexed by discriminant
+    pub const ALL: [Self; 8] = [Self::Shaded, Self::Depth, Self::Normal, Self::OctreeDepth, Self::Steps, Self::NodeHash, Self::HitMask, Self::PathTraced];
+
+    /// Next mode for the runtime toggle
+    pub fn next(self) -> Self {
+        match self {
+            Self::Shaded => Self::Depth,
+            Self::Depth => Self::Normal,
+            Self::Normal => Self::OctreeDepth,
+            Self::OctreeDepth => Self::Steps,
+            Self::Steps => Self::NodeHash,
+            Self::NodeHash => Self::HitMask,
+            Self::HitMask => Self::PathTraced,
+            Self::PathTraced => Self::Shaded
+        }
+    }
+}
+
+impl FromStr for ViewMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shaded" => Ok(Self::Shaded),
+            "depth" => Ok(Self::Depth),
+            "normal" => Ok(Self::Normal),
+            "octree-depth" => Ok(Self::OctreeDepth),
+            "steps" => Ok(Self::Steps),
+            "node-hash" => Ok(Self::NodeHash),
+            "hit-mask" => Ok(Self::HitMask),
+            "path-traced" => Ok(Self::PathTraced),
+
+            _ => Err(())
+        }
+    }
+}
+
+impl fmt::Display for ViewMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Shaded => "shaded",
+            Self::Depth => "depth",
+            Self::Normal => "normal",
+            Self::OctreeDepth => "octree-depth",
+            Self::Steps => "steps",
+            Self::NodeHash => "node-hash",
+            Self::HitMask => "hit-mask",
+            Self::PathTraced => "path-traced"
+        })
+    }
+}
+
+
+/// Rays per pixel and where they go. Discriminants must match AA_* in rendering_shader.comp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Antialiasing {
+    /// One ray through the pixel centre
+    #[default]
+    None = 0,
+    /// Four rays on a regular grid
+    Grid2x2 = 1,
+    /// Four rays on a rotated grid, better on near horizontal and vertical edges
+    Rgss = 2
+}
+
+impl Antialiasing {
+    /// Indexed by discriminant
+    pub const ALL: [Self; 3] = [Self::None, Self::Grid2x2, Self::Rgss];
+
+    pub fn sample_count(self) -> u32 {
+        match self {
+            Self::None => 1,
+            Self::Grid2x2 | Self::Rgss => 4
+        }
+    }
+}
+
+impl FromStr for Antialiasing {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "2x2" => Ok(Self::Grid2x2),
+            "rgss" => Ok(Self::Rgss),
+
+            _ => Err(())
+        }
+    }
+}
+
+impl fmt::Display for Antialiasing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Grid2x2 => "2x2",
+            Self::Rgss => "rgss"
+        })
+    }
+}
+
+
+/// Maps HDR radiance to the display range. Discriminants must match TONEMAP_* in rendering_shader.comp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tonemapper {
+    /// Clamps, everything above one is white
+    None = 0,
+    Reinhard = 1,
+    /// Narkowicz's fit of the ACES filmic curve
+    #[default]
+    Aces = 2
+}
+
+impl Tonemapper {
+    /// Indexed by discriminant
+    pub const ALL: [Self; 3] = [Self::None, Self::Reinhard, Self::Aces];
+
+    /// Next tonemapper for the runtime toggle
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::Reinhard,
+            Self::Reinhard => Self::Aces,
+            Self::Aces => Self::None
+        }
+    }
+}
+
+impl FromStr for Tonemapper {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "reinhard" => Ok(Self::Reinhard),
+            "aces" => Ok(Self::Aces),
+
+            _ => Err(())
+        }
+    }
+}
+
+impl fmt::Display for Tonemapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Reinhard => "reinhard",
+            Self::Aces => "aces"
+        })
+    }
+}
+
+
+/// Where the octree comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scene {
+    /// Built in test tree, five children per node
+    #[default]
+    Fractal,
+    /// Noise terrain, see `TerrainParams`
+    Terrain
+}
+
+impl FromStr for Scene {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fractal" => Ok(Self::Fractal),
+            "terrain" => Ok(Self::Terrain),
+
+            _ => Err(())
+        }
+    }
+}
+
+impl fmt::Display for Scene {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Fractal => "fractal",
+            Self::Terrain => "terrain"
+        })
+    }
+}
+
+
+/// Which voxels of a mesh are solid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MeshFill {
+    /// Only voxels touching a triangle
+    #[default]
+    Surface,
+    /// Also voxels behind an odd number of triangles
+    Parity,
+    /// Also voxels the surface winds around a nonzero number of times, tolerates overlapping parts
+    Winding
+}
+
+impl FromStr for MeshFill {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "surface" => Ok(Self::Surface),
+            "parity" => Ok(Self::Parity),
+            "winding" => Ok(Self::Winding),
+
+            _ => Err(())
+        }
+    }
+}
+
+impl fmt::Display for MeshFill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Surface => "surface",
+            Self::Parity => "parity",
+            Self::Winding => "winding"
+        })
+    }
+}
+
+
+/// Parameters of the noise terrain generator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainParams {
+    pub seed: u32,
+    /// Layers below the root, the terrain is 2^depth voxels wide
+    pub depth: u32,
+    /// Noise layers summed for the height, each at double the frequency and half the amplitude
+    pub octaves: u32,
+    /// Hills across the whole terrain on the first octave
+    pub frequency: f32,
+    /// Height up to which empty space is water, 0 - 1
+    pub sea_level: f32,
+    /// Half width of cave tunnels in noise units, zero disables caves
+    pub caves: f32,
+    /// Voxels of dirt between the grass and the stone
+    pub dirt: u32
+}
+
+impl Default for TerrainParams {
+    fn default() -> Self {
+        Self { seed: 1, depth: 6, octaves: 5, frequency: 2.0, sea_level: 0.35, caves: 0.0, dirt: 3 }
+    }
+}
+
+/// Comma separated `key=value` pairs, missing keys keep their default
+impl FromStr for TerrainParams {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut params = Self::default();
+
+        for pair in s.split(',') {
+            let (key, value) = pair.split_once('=').ok_or(())?;
+            let value = value.trim();
+
+            match key.trim() {
+                "seed" => params.seed = value.parse().map_err(| _ | ())?,
+                "depth" => params.depth = value.parse().map_err(| _ | ())?,
+                "octaves" => params.octaves = value.parse().map_err(| _ | ())?,
+                "frequency" => params.frequency = value.parse().map_err(| _ | ())?,
+                "sea-level" => params.sea_level = value.parse().map_err(| _ | ())?,
+                "caves" => params.caves = value.parse().map_err(| _ | ())?,
+                "dirt" => params.dirt = value.parse().map_err(| _ | ())?,
+
+                _ => return Err(())
+            }
+        }
+
+        let is_valid = (1..=MAX_TERRAIN_DEPTH).contains(&params.depth)
+            && params.octaves > 0
+            && params.frequency > 0.0 && params.frequency.is_finite()
+            && (0.0..=1.0).contains(&params.sea_level)
+            && params.caves >= 0.0 && params.caves.is_finite();
+
+        if !is_valid {
+            return Err(());
+        }
+
+        Ok(params)
+    }
+}
+
+
+/// Samples along each axis of a raw volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VolumeSize {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32
+}
+
+impl FromStr for VolumeSize {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('x').map(| part | part.parse::<u32>());
+
+        let size = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(width)), Some(Ok(height)), Some(Ok(depth)), None) => Self { width, height, depth },
+
+            _ => return Err(())
+        };
+
+        if size.width == 0 || size.height == 0 || size.depth == 0 {
+            return Err(());
+        }
+
+        Ok(size)
+    }
+}
+
+
+/// Colour of the volume samples from `value` up to the next stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferStop {
+    /// 0 - 1 of the sample range, as 16 bit
+    pub value: u16,
+    /// sRGB
+    pub color: [u8; 3]
+}
+
+/// Which volume samples are solid and their colour
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transfer {
+    /// Samples at or above it are solid and all the same colour
+    Threshold(u16),
+    /// Ascending, samples below the first stop are empty
+    Stops(Vec<TransferStop>)
+}
+
+impl Default for Transfer {
+    fn default() -> Self {
+        Self::Threshold(u16::MAX / 2 + 1)
+    }
+}
+
+/// A single number is a threshold, otherwise comma separated `value:#rrggbb` stops
+impl FromStr for Transfer {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_value = | value: &str | match value.trim().parse::<f32>() {
+            Ok(value) if (0.0..=1.0).contains(&value) => Ok((value * u16::MAX as f32).round() as u16),
+
+            _ => Err(())
+        };
+
+        if !s.contains(':') {
+            return Ok(Self::Threshold(parse_value(s)?));
+        }
+
+        let stops = s.split(',')
+            .map(| stop | {
+                let (value, color) = stop.split_once(':').ok_or(())?;
+                let hex = color.trim().strip_prefix('#').filter(| hex | hex.len() == 6 && hex.is_ascii()).ok_or(())?;
+                let channel = | idx: usize | u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).map_err(| _ | ());
+
+                Ok(TransferStop { value: parse_value(value)?, color: [channel(0)?, channel(1)?, channel(2)?] })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let is_valid = stops.len() <= MAX_TRANSFER_STOPS
+            && stops.windows(2).all(| pair | pair[0].value < pair[1].value);
+
+        if !is_valid {
+            return Err(());
+        }
+
+        Ok(Self::Stops(stops))
+    }
+}
+
+
+/// How the octree is encoded in GPU memory. Discriminants must match NODE_LAYOUT_* in rendering_shader.comp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NodeLayout {
+    /// `VoxelData`, eight child indices per node
+    #[default]
+    Full = 0,
+    /// `CompactNode`, child masks and a pointer to the first of the contiguous children
+    Compact = 1
+}
+
+impl FromStr for NodeLayout {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Self::Full),
+            "compact" => Ok(Self::Compact),
+
+            _ => Err(())
+        }
+    }
+}
+
+impl fmt::Display for NodeLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Full => "full",
+            Self::Compact => "compact"
+        })
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Command {
+    /// Open a window and render
+    #[default]
+    View,
+    /// Print octree statistics instead
+    Inspect
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `--help` was passed, not really an error
+    HelpRequested,
+    UnknownArgument(String),
+    MissingValue(String),
+    InvalidValue { arg: String, value: String }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HelpRequested => f.write_str(USAGE),
+            Self::UnknownArgument(arg) => write!(f, "unknown argument {arg:?}"),
+            Self::MissingValue(arg) => write!(f, "{arg} requires a value"),
+            Self::InvalidValue { arg, value } => write!(f, "invalid value {value:?} for {arg}")
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub command: Command,
+    pub window_size: Extent,
+    /// Render target size relative to window size
+    pub render_scale: f32,
+    pub present_mode: PresentModePreference,
+    pub view_mode: ViewMode,
+    pub antialiasing: Antialiasing,
+    /// Move the samples inside the pixel every frame and accumulate the result
+    pub jitter: bool,
+    /// Stops, every one doubles the brightness
+    pub exposure: f32,
+    pub tonemapper: Tonemapper,
+    /// Azimuth and elevation in degrees
+    pub sun_angles: Floats<2>,
+    pub sun_color: Floats<3>,
+    pub ambient_color: Floats<3>,
+    /// Extinction per unit of distance
+    pub fog_density: f32,
+    pub ao_samples: u32,
+    pub ao_radius: f32,
+    /// Path tracer bounces after the first hit
+    pub max_bounces: u32,
+    /// Projected node size in pixels below which camera rays stop descending
+    pub lod_pixels: f32,
+    pub node_layout: NodeLayout,
+    pub scene: Scene,
+    /// Used by `Scene::Terrain`
+    pub terrain: TerrainParams,
+    /// Import this heightmap instead of building `scene`
+    pub heightmap: Option<PathBuf>,
+    /// Colours of the heightmap columns
+    pub color_map: Option<PathBuf>,
+    /// Height of a white heightmap pixel, in map widths
+    pub height_scale: f32,
+    /// Voxelize this mesh instead of building `scene`
+    pub mesh: Option<PathBuf>,
+    /// Layers below the root, the longest side of the mesh is 2^mesh_depth voxels
+    pub mesh_depth: u32,
+    pub mesh_fill: MeshFill,
+    /// Import this point cloud instead of building `scene`
+    pub points: Option<PathBuf>,
+    /// Layers below the root, the longest side of the point cloud is 2^point_depth voxels
+    pub point_depth: u32,
+    /// Import this raw volume or directory of slices instead of building `scene`
+    pub volume: Option<PathBuf>,
+    /// Needed for raw volumes, slices tell their size themselves
+    pub volume_size: Option<VolumeSize>,
+    /// 8 or 16
+    pub volume_bits: u32,
+    pub transfer: Transfer,
+    /// Palette size imported colours are quantized to
+    pub palette_colors: u32,
+    /// Render a CPU reference image here instead of opening a window
+    pub cpu_reference: Option<PathBuf>,
+    /// Pixels per compute workgroup
+    pub tile_size: Extent,
+    /// Run for this many seconds and print frame statistics
+    pub benchmark: Option<f32>
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            command: Default::default(),
+            window_size: Extent { width: 600, height: 400 },
+            render_scale: 1.0,
+            present_mode: Default::default(),
+            view_mode: Default::default(),
+            antialiasing: Default::default(),
+            jitter: false,
+            exposure: 0.0,
+            tonemapper: Default::default(),
+            sun_angles: Floats([30.0, 50.0]),
+            sun_color: Floats([1.0, 0.95, 0.85]),
+            ambient_color: Floats([0.12, 0.14, 0.18]),
+            fog_density: 0.04,
+            ao_samples: 8,
+            ao_radius: 0.5,
+            max_bounces: 4,
+            lod_pixels: 1.0,
+            node_layout: Default::default(),
+            scene: Default::default(),
+            terrain: Default::default(),
+            heightmap: None,
+            color_map: None,
+            height_scale: 0.25,
+            mesh: None,
+            mesh_depth: 7,
+            mesh_fill: Default::default(),
+            points: None,
+            point_depth: 10,
+            volume: None,
+            volume_size: None,
+            volume_bits: 8,
+            transfer: Default::default(),
+            palette_colors: 16,
+            cpu_reference: None,
+            tile_size: Extent { width: 8, height: 8 },
+            benchmark: None
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(arg: &str, value: Option<String>) -> Result<T, ConfigError> {
+    let value = value.ok_or_else(|| ConfigError::MissingValue(arg.to_owned()))?;
+
+    value.parse()
+        .map_err(| _ | ConfigError::InvalidValue { arg: arg.to_owned(), value })
+}
+
+impl Config {
+    /// `args` should not contain the program name
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "inspect" => config.command = Command::Inspect,
+                "--size" => config.window_size = parse_value(&arg, args.next())?,
+                "--render-scale" => {
+                    let value = args.next();
+                    config.render_scale = parse_value(&arg, value.clone())?;
+
+                    if !RENDER_SCALE_RANGE.contains(&config.render_scale) {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--present-mode" => config.present_mode = parse_value(&arg, args.next())?,
+                "--view" => config.view_mode = parse_value(&arg, args.next())?,
+                "--aa" => config.antialiasing = parse_value(&arg, args.next())?,
+                "--jitter" => config.jitter = true,
+                "--exposure" => {
+                    let value = args.next();
+                    config.exposure = parse_value(&arg, value.clone())?;
+
+                    if !config.exposure.is_finite() {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--tonemapper" => config.tonemapper = parse_value(&arg, args.next())?,
+                "--sun" => config.sun_angles = parse_value(&arg, args.next())?,
+                "--sun-color" => config.sun_color = parse_value(&arg, args.next())?,
+                "--ambient" => config.ambient_color = parse_value(&arg, args.next())?,
+                "--fog" => {
+                    let value = args.next();
+                    config.fog_density = parse_value(&arg, value.clone())?;
+
+                    if !(config.fog_density >= 0.0 && config.fog_density.is_finite()) {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--ao-samples" => {
+                    let value = args.next();
+                    config.ao_samples = parse_value(&arg, value.clone())?;
+
+                    if config.ao_samples > MAX_AO_SAMPLES {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--ao-radius" => {
+                    let value = args.next();
+                    config.ao_radius = parse_value(&arg, value.clone())?;
+
+                    if !(config.ao_radius > 0.0 && config.ao_radius.is_finite()) {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--bounces" => {
+                    let value = args.next();
+                    config.max_bounces = parse_value(&arg, value.clone())?;
+
+                    if config.max_bounces > MAX_BOUNCES {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--scene" => config.scene = parse_value(&arg, args.next())?,
+                "--terrain" => config.terrain = parse_value(&arg, args.next())?,
+                "--heightmap" => config.heightmap = Some(parse_value(&arg, args.next())?),
+                "--color-map" => config.color_map = Some(parse_value(&arg, args.next())?),
+                "--height-scale" => {
+                    let value = args.next();
+                    config.height_scale = parse_value(&arg, value.clone())?;
+
+                    if !(config.height_scale > 0.0 && config.height_scale.is_finite()) {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--mesh" => config.mesh = Some(parse_value(&arg, args.next())?),
+                "--mesh-depth" => {
+                    let value = args.next();
+                    config.mesh_depth = parse_value(&arg, value.clone())?;
+
+                    if !(1..=MAX_MESH_DEPTH).contains(&config.mesh_depth) {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--fill" => config.mesh_fill = parse_value(&arg, args.next())?,
+                "--points" => config.points = Some(parse_value(&arg, args.next())?),
+                "--point-depth" => {
+                    let value = args.next();
+                    config.point_depth = parse_value(&arg, value.clone())?;
+
+                    if !(1..=MAX_POINT_DEPTH).contains(&config.point_depth) {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--volume" => config.volume = Some(parse_value(&arg, args.next())?),
+                "--volume-size" => config.volume_size = Some(parse_value(&arg, args.next())?),
+                "--volume-bits" => {
+                    let value = args.next();
+                    config.volume_bits = parse_value(&arg, value.clone())?;
+
+                    if ![8, 16].contains(&config.volume_bits) {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--transfer" => config.transfer = parse_value(&arg, args.next())?,
+                "--colors" => {
+                    let value = args.next();
+                    config.palette_colors = parse_value(&arg, value.clone())?;
+
+                    if !(1..=MAX_PALETTE_COLORS).contains(&config.palette_colors) {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--lod" => {
+                    let value = args.next();
+                    config.lod_pixels = parse_value(&arg, value.clone())?;
+
+                    if !(config.lod_pixels >= 0.0 && config.lod_pixels.is_finite()) {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--node-layout" => config.node_layout = parse_value(&arg, args.next())?,
+                "--cpu-reference" => config.cpu_reference = Some(parse_value(&arg, args.next())?),
+                "--tile-size" => {
+                    let value = args.next();
+                    config.tile_size = parse_value(&arg, value.clone())?;
+
+                    if config.tile_size.width.checked_mul(config.tile_size.height).is_none_or(| invocations | invocations > MAX_TILE_INVOCATIONS) {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+                },
+                "--benchmark" => {
+                    let value = args.next();
+                    let seconds: f32 = parse_value(&arg, value.clone())?;
+
+                    if !(seconds > 0.0 && seconds.is_finite()) {
+                        return Err(ConfigError::InvalidValue { arg, value: value.unwrap() });
+                    }
+
+                    config.benchmark = Some(seconds);
+                },
+                "-h" | "--help" => return Err(ConfigError::HelpRequested),
+
+                _ => return Err(ConfigError::UnknownArgument(arg))
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+
+
+#[test]
+fn test_config_parsing() {
+    let parse = | args: &[&str] | Config::from_args(args.iter().map(| arg | arg.to_string()));
+
+    assert_eq!(parse(&[]), Ok(Config::default()));
+    assert_eq!(parse(&["inspect", "--node-layout", "compact"]).map(| c | (c.command, c.node_layout)), Ok((Command::Inspect, NodeLayout::Compact)));
+    assert_eq!(parse(&["--present-mode", "mailbox"]).unwrap().present_mode, PresentModePreference::Mailbox);
+    assert_eq!(parse(&["--size", "1280x720"]).unwrap().window_size, Extent { width: 1280, height: 720 });
+    assert_eq!(parse(&["--render-scale", "0.5"]).unwrap().render_scale, 0.5);
+    assert!(ViewMode::ALL.iter().enumerate().all(| (idx, &mode) | mode as usize == idx));
+    assert_eq!(parse(&["--view", "octree-depth"]).unwrap().view_mode, ViewMode::OctreeDepth);
+    assert_eq!(parse(&["--view", "path-traced", "--bounces", "8"]).map(| c | (c.view_mode, c.max_bounces)), Ok((ViewMode::PathTraced, 8)));
+    assert_eq!(parse(&["--aa", "rgss", "--jitter"]).map(| c | (c.antialiasing, c.jitter)), Ok((Antialiasing::Rgss, true)));
+    assert_eq!(parse(&["--exposure", "-1.5", "--tonemapper", "reinhard"]).map(| c | (c.exposure, c.tonemapper)), Ok((-1.5, Tonemapper::Reinhard)));
+    assert_eq!(parse(&["--sun", "90, -10"]).unwrap().sun_angles, Floats([90.0, -10.0]));
+    assert_eq!(parse(&["--ao-samples", "0"]).unwrap().ao_samples, 0);
+    assert_eq!(parse(&["--fog", "0"]).unwrap().fog_density, 0.0);
+    assert_eq!(parse(&["--scene", "terrain"]).unwrap().scene, Scene::Terrain);
+    assert_eq!(
+        parse(&["--terrain", "depth=4, sea-level=0.5,seed=7"]).unwrap().terrain,
+        TerrainParams { depth: 4, sea_level: 0.5, seed: 7, ..Default::default() }
+    );
+    assert_eq!(
+        parse(&["--heightmap", "hills.png", "--color-map", "hills.ppm", "--colors", "8"]).map(| c | (c.heightmap, c.color_map, c.palette_colors)),
+        Ok((Some(PathBuf::from("hills.png")), Some(PathBuf::from("hills.ppm")), 8))
+    );
+    assert_eq!(
+        parse(&["--mesh", "teapot.obj", "--mesh-depth", "9", "--fill", "winding"]).map(| c | (c.mesh, c.mesh_depth, c.mesh_fill)),
+        Ok((Some(PathBuf::from("teapot.obj")), 9, MeshFill::Winding))
+    );
+    assert_eq!(
+        parse(&["--points", "scan.ply", "--point-depth", "12"]).map(| c | (c.points, c.point_depth)),
+        Ok((Some(PathBuf::from("scan.ply")), 12))
+    );
+    assert_eq!(
+        parse(&["--volume", "head.raw", "--volume-size", "256x256x99", "--volume-bits", "16"]).map(| c | (c.volume, c.volume_size, c.volume_bits)),
+        Ok((Some(PathBuf::from("head.raw")), Some(VolumeSize { width: 256, height: 256, depth: 99 }), 16))
+    );
+    assert_eq!(parse(&["--transfer", "1"]).unwrap().transfer, Transfer::Threshold(u16::MAX));
+    assert_eq!(
+        parse(&["--transfer", "0.2:#ff8000, 0.6:#FFFFFF"]).unwrap().transfer,
+        Transfer::Stops(vec![TransferStop { value: 13107, color: [255, 128, 0] }, TransferStop { value: 39321, color: [255; 3] }])
+    );
+    assert_eq!(parse(&["--lod", "2.5"]).unwrap().lod_pixels, 2.5);
+    assert_eq!(parse(&["--node-layout", "compact"]).unwrap().node_layout, NodeLayout::Compact);
+    assert_eq!(parse(&["--cpu-reference", "ref.ppm"]).unwrap().cpu_reference, Some(PathBuf::from("ref.ppm")));
+    assert_eq!(parse(&["--tile-size", "16x8"]).unwrap().tile_size, Extent { width: 16, height: 8 });
+    assert_eq!(parse(&["--benchmark", "10"]).unwrap().benchmark, Some(10.0));
+
+    assert_eq!(parse(&["--size", "1280"]), Err(ConfigError::InvalidValue { arg: "--size".into(), value: "1280".into() }));
+    assert_eq!(parse(&["--render-scale", "8"]), Err(ConfigError::InvalidValue { arg: "--render-scale".into(), value: "8".into() }));
+    assert_eq!(parse(&["--sun-color", "1,1"]), Err(ConfigError::InvalidValue { arg: "--sun-color".into(), value: "1,1".into() }));
+    assert_eq!(parse(&["--ambient", "0,0,0,0"]), Err(ConfigError::InvalidValue { arg: "--ambient".into(), value: "0,0,0,0".into() }));
+    assert_eq!(parse(&["--aa", "8x"]), Err(ConfigError::InvalidValue { arg: "--aa".into(), value: "8x".into() }));
+    assert_eq!(parse(&["--exposure", "inf"]), Err(ConfigError::InvalidValue { arg: "--exposure".into(), value: "inf".into() }));
+    assert_eq!(parse(&["--tonemapper", "filmic"]), Err(ConfigError::InvalidValue { arg: "--tonemapper".into(), value: "filmic".into() }));
+    assert_eq!(parse(&["--fog", "-0.1"]), Err(ConfigError::InvalidValue { arg: "--fog".into(), value: "-0.1".into() }));
+    assert_eq!(parse(&["--terrain", "depth=12"]), Err(ConfigError::InvalidValue { arg: "--terrain".into(), value: "depth=12".into() }));
+    assert_eq!(parse(&["--terrain", "hills=3"]), Err(ConfigError::InvalidValue { arg: "--terrain".into(), value: "hills=3".into() }));
+    assert_eq!(parse(&["--height-scale", "0"]), Err(ConfigError::InvalidValue { arg: "--height-scale".into(), value: "0".into() }));
+    assert_eq!(parse(&["--colors", "300"]), Err(ConfigError::InvalidValue { arg: "--colors".into(), value: "300".into() }));
+    assert_eq!(parse(&["--mesh-depth", "11"]), Err(ConfigError::InvalidValue { arg: "--mesh-depth".into(), value: "11".into() }));
+    assert_eq!(parse(&["--point-depth", "0"]), Err(ConfigError::InvalidValue { arg: "--point-depth".into(), value: "0".into() }));
+    assert_eq!(parse(&["--volume-size", "64x64"]), Err(ConfigError::InvalidValue { arg: "--volume-size".into(), value: "64x64".into() }));
+    assert_eq!(parse(&["--volume-bits", "12"]), Err(ConfigError::InvalidValue { arg: "--volume-bits".into(), value: "12".into() }));
+    assert_eq!(parse(&["--transfer", "0.6:#ffffff,0.2:#000000"]), Err(ConfigError::InvalidValue { arg: "--transfer".into(), value: "0.6:#ffffff,0.2:#000000".into() }));
+    assert_eq!(parse(&["--transfer", "1.5"]), Err(ConfigError::InvalidValue { arg: "--transfer".into(), value: "1.5".into() }));
+    assert_eq!(parse(&["--fill", "solid"]), Err(ConfigError::InvalidValue { arg: "--fill".into(), value: "solid".into() }));
+    assert_eq!(parse(&["--lod", "nan"]), Err(ConfigError::InvalidValue { arg: "--lod".into(), value: "nan".into() }));
+    assert_eq!(parse(&["--node-layout", "sparse"]), Err(ConfigError::InvalidValue { arg: "--node-layout".into(), value: "sparse".into() }));
+    assert_eq!(parse(&["--ao-samples", "100"]), Err(ConfigError::InvalidValue { arg: "--ao-samples".into(), value: "100".into() }));
+    assert_eq!(parse(&["--ao-radius", "-1"]), Err(ConfigError::InvalidValue { arg: "--ao-radius".into(), value: "-1".into() }));
+    assert_eq!(parse(&["--tile-size", "64x64"]), Err(ConfigError::InvalidValue { arg: "--tile-size".into(), value: "64x64".into() }));
+    assert_eq!(parse(&["--tile-size", "65536x65536"]), Err(ConfigError::InvalidValue { arg: "--tile-size".into(), value: "65536x65536".into() }));
+    assert_eq!(parse(&["--benchmark", "nan"]), Err(ConfigError::InvalidValue { arg: "--benchmark".into(), value: "nan".into() }));
+    assert_eq!(parse(&["--benchmark", "inf"]), Err(ConfigError::InvalidValue { arg: "--benchmark".into(), value: "inf".into() }));
+
+    assert_eq!(parse(&["--present-mode"]), Err(ConfigError::MissingValue("--present-mode".into())));
+    assert_eq!(parse(&["--present-mode", "fast"]), Err(ConfigError::InvalidValue { arg: "--present-mode".into(), value: "fast".into() }));
+    assert_eq!(parse(&["--fullscreen"]), Err(ConfigError::UnknownArgument("--fullscreen".into())));
+}
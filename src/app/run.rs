@@ -1,77 +1,140 @@
-use std::{sync::Arc, time::Instant};
+use std::{io, path::Path, sync::Arc, time::Instant};
 
-use qubicon_vulkan::{commands::command_buffers::{command_buffer_builder::{barrier::{AccessFlags, ImageMemoryBarrier, PipelineBarrierDependencyFlags}, copy::BufferCopy, PipelineBindPoint}, CommandBufferUsageFlags}, descriptors::alloc::descriptor_set::{BufferWriteInfo, DescriptorSet, DescriptorWrite, ImageWriteInfo}, instance::physical_device::memory_properties::MemoryTypeProperties, memory::{alloc::{hollow_device_memory_allocator::HollowDeviceMemoryAllocator, standart_device_memory_allocator::StandartMemoryAllocator}, resources::{buffer::{Buffer, BufferCreateInfo, BufferUsageFlags}, image::ImageLayout, image_view::{ImageAspect, ImageSubresourceRange, ImageViewCreateInfo, ImageViewType}}, BufferRequest, BufferStagingBufferInfo}, queue::{PresentInfo, PresentInfoSwapchainEntry}, shaders::PipelineStageFlags, swapchain::AcquireImageSyncPrimitive, sync};
+use qubicon_vulkan::{commands::command_buffers::{command_buffer_builder::{barrier::{AccessFlags, ImageMemoryBarrier, PipelineBarrierDependencyFlags}, copy::{BufferCopy, Filter, ImageBlit, ImageSubresourceLayers}, PipelineBindPoint}, CommandBufferUsageFlags}, descriptors::alloc::descriptor_set::{BufferWriteInfo, DescriptorSet, DescriptorWrite, ImageWriteInfo}, instance::physical_device::memory_properties::MemoryTypeProperties, memory::{alloc::{hollow_device_memory_allocator::HollowDeviceMemoryAllocator, standart_device_memory_allocator::StandartMemoryAllocator}, resources::{buffer::{Buffer, BufferCreateInfo, BufferUsageFlags}, format::Format, image::{Image, ImageCreateInfo, ImageLayout, ImageType, ImageUsageFlags}, image_view::{ImageAspect, ImageSubresourceRange, ImageViewCreateInfo, ImageViewType}}, BufferRequest, BufferStagingBufferInfo}, queue::{PresentInfo, PresentInfoSwapchainEntry}, shaders::PipelineStageFlags, swapchain::AcquireImageSyncPrimitive, sync};
 use qubicon_windowing::x11::WindowEvent;
 
-use self::gpu_shared_data::{CameraData, VoxelData};
+use self::{gpu_layout::{GpuType, LayoutRules}, gpu_shared_data::{PaletteEntry, PostSettings, RenderData, RenderSettings, VoxelData, ACCUMULATION_BINDING, COMPACT_NODES_BINDING, HDR_TARGET_BINDING, PALETTE_BINDING, RENDER_DATA_BINDING, RENDER_TARGET_BINDING, VOXEL_DATA_BINDING}};
 
-use super::Application;
+use self::frame_stats::FrameStats;
 
+use super::{config::{Config, Extent, NodeLayout, Scene}, Application, VulkanContext};
+
+mod builder;
 mod camera;
+mod compact;
+mod cpu_reference;
+mod dag;
+mod frame_stats;
+mod gpu_layout;
 mod gpu_shared_data;
+mod import;
+mod inspect;
+mod lighting;
+mod lod;
+mod raycast;
+mod terrain;
+mod validate;
 mod voxel_data_generator;
 
+pub(super) use self::gpu_shared_data::{NODE_LAYOUT_SPEC_ID, PASS_POST, PASS_RENDER, PASS_SPEC_ID, RENDER_SET_LAYOUT, TILE_HEIGHT_SPEC_ID, TILE_WIDTH_SPEC_ID};
+
 const MOVE_SPEED_MULTIPLIER: f32 = 2.0;
+// radians per second
+const SUN_SPEED: f32 = 1.0;
+// stops per second
+const EXPOSURE_SPEED: f32 = 2.0;
+const GENERATED_TREE_LAYERS: u8 = 4;
+// must match the format qualifiers of render_target, accumulation and hdr_target in the shader
+const RENDER_TARGET_FORMAT: Format = Format::R8G8B8A8_UNORM;
+const ACCUMULATION_FORMAT: Format = Format::R32G32B32A32_SFLOAT;
+const HDR_TARGET_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+// path traced frames averaged by --cpu-reference
+const CPU_REFERENCE_FRAMES: u32 = 64;
+
+fn color_subresource_range() -> ImageSubresourceRange {
+    ImageSubresourceRange {
+        aspect_mask: ImageAspect::COLOR,
+        mip_levels: 0..1,
+        array_layers: 0..1
+    }
+}
 
-impl Application {
-    fn instantiate_resources(&mut self) -> (Buffer<StandartMemoryAllocator>, Buffer<StandartMemoryAllocator>, Arc<DescriptorSet>) {
-        let generated_tree = voxel_data_generator::generate_tree(4);
+fn color_subresource_layers() -> ImageSubresourceLayers {
+    ImageSubresourceLayers {
+        aspect_mask: ImageAspect::COLOR,
+        mip_level: 0,
+        array_layers: 0..1
+    }
+}
 
-        let voxel_staging_buffer = self.vk_ctx.device.create_buffer(
+impl Application {
+    fn create_staging_buffer<T: GpuType>(&self, data: &[T]) -> Buffer<StandartMemoryAllocator> {
+        let staging_buffer = self.vk_ctx.device.create_buffer(
             Arc::clone(&self.vk_ctx.allocator),
             MemoryTypeProperties::HOST_VISIBLE,
             &BufferCreateInfo {
                 usage_flags: BufferUsageFlags::TRANSFER_SRC,
-                size: (core::mem::size_of::<VoxelData>() * generated_tree.len()) as u64,
+                size: (LayoutRules::Std430.array_stride(T::STD430) * data.len()) as u64,
                 main_owner_queue_family: self.vk_ctx.queue_family,
 
                 ..Default::default()
             }
-        ).expect("failed to create staging buffer for voxel data");
+        ).expect("failed to create staging buffer");
 
         unsafe {
-            voxel_staging_buffer.map::<VoxelData>()
-                .unwrap()
-                .iter_mut()
-                .zip(generated_tree.iter())
-                .for_each(| (dst, src) | { dst.write(*src); });
+            let mut mapped = staging_buffer.map::<u8>().unwrap();
+
+            gpu_layout::write_mapped_array(LayoutRules::Std430, data, &mut mapped);
         }
 
+        staging_buffer
+    }
+
+    // device local copies of the staging buffers, in the same order
+    fn upload_storage_buffers(&self, staging_buffers: &[&Buffer<StandartMemoryAllocator>]) -> Vec<Buffer<StandartMemoryAllocator>> {
         let mut order = self.vk_ctx.resource_factory.create_order(Arc::clone(&self.vk_ctx.allocator))
             .unwrap();
 
-        order.request_buffer(
-            MemoryTypeProperties::DEVICE_LOCAL,
-            BufferRequest {
-                usage_flags: BufferUsageFlags::STORAGE_BUFFER,
-                create_flags: Default::default(),
-                size: voxel_staging_buffer.size(),
-                main_owner_queue_family: self.vk_ctx.queue_family,
-                staging_buffer: Some(
-                    BufferStagingBufferInfo {
-                        buffer: &voxel_staging_buffer,
-                        regions: &[
-                            BufferCopy {
-                                src_offset: 0,
-                                dst_offset: 0,
-                                size: voxel_staging_buffer.size()
-                            }
-                        ]
-                    }
-                )
-            }
-        ).unwrap();
+        for &staging_buffer in staging_buffers {
+            order.request_buffer(
+                MemoryTypeProperties::DEVICE_LOCAL,
+                BufferRequest {
+                    usage_flags: BufferUsageFlags::STORAGE_BUFFER,
+                    create_flags: Default::default(),
+                    size: staging_buffer.size(),
+                    main_owner_queue_family: self.vk_ctx.queue_family,
+                    staging_buffer: Some(
+                        BufferStagingBufferInfo {
+                            buffer: staging_buffer,
+                            regions: &[
+                                BufferCopy {
+                                    src_offset: 0,
+                                    dst_offset: 0,
+                                    size: staging_buffer.size()
+                                }
+                            ]
+                        }
+                    )
+                }
+            ).unwrap();
+        }
+
+        // buffers come back in request order
+        order.do_order().unwrap().wait().1
+    }
 
-        let order = order.do_order().unwrap();
+    // in the layout the pipelines were specialized for
+    fn create_octree_staging_buffer(&self, octree: &[VoxelData]) -> Buffer<StandartMemoryAllocator> {
+        match self.config.node_layout {
+            NodeLayout::Full => self.create_staging_buffer(octree),
+            NodeLayout::Compact => self.create_staging_buffer(&compact::from_voxel_data(octree))
+        }
+    }
 
+    fn instantiate_resources(&mut self, octree: &[VoxelData], palette: &[PaletteEntry]) -> (Buffer<StandartMemoryAllocator>, [Buffer<StandartMemoryAllocator>; 2], Arc<DescriptorSet>) {
+        let voxel_staging_buffer = self.create_octree_staging_buffer(octree);
+        let palette_staging_buffer = self.create_staging_buffer(palette);
 
+        let mut buffers = self.upload_storage_buffers(&[&voxel_staging_buffer, &palette_staging_buffer]);
+        let palette_buffer = buffers.pop().unwrap();
+        let voxel_buffer = buffers.pop().unwrap();
 
         let uniform_buffer = self.vk_ctx.device.create_buffer(
             Arc::clone(&self.vk_ctx.allocator),
             MemoryTypeProperties::HOST_VISIBLE | MemoryTypeProperties::HOST_COHERENT,
             &BufferCreateInfo {
                 usage_flags: BufferUsageFlags::UNIFORM_BUFFER,
-                size: core::mem::size_of::<CameraData>() as u64,
+                size: RenderData::STD140.size as u64,
                 main_owner_queue_family: self.vk_ctx.queue_family,
 
                 ..Default::default()
@@ -82,14 +145,11 @@ impl Application {
             self.descriptor_pool.allocate_descriptor_set_unchecked(Arc::clone(&self.descriptor_set_layout))
         }.expect("failed to allocate descriptor set");
 
-        let voxel_buffer = order.wait().1.swap_remove(0);
-
-
         unsafe {
             descriptor_set.update_unchecked(
                 &[
                     DescriptorWrite {
-                        binding: 0,
+                        binding: RENDER_DATA_BINDING,
                         index: 0,
                         write_info: BufferWriteInfo {
                             buffer: &uniform_buffer,
@@ -98,19 +158,89 @@ impl Application {
                         }
                     },
                     DescriptorWrite {
-                        binding: 1,
+                        binding: VOXEL_DATA_BINDING,
                         index: 0,
                         write_info: BufferWriteInfo {
                             buffer: &voxel_buffer,
                             offset: 0,
                             len: voxel_buffer.size()
                         }
+                    },
+                    // the shader only reads the binding of the configured layout, the other one just has to be valid
+                    DescriptorWrite {
+                        binding: COMPACT_NODES_BINDING,
+                        index: 0,
+                        write_info: BufferWriteInfo {
+                            buffer: &voxel_buffer,
+                            offset: 0,
+                            len: voxel_buffer.size()
+                        }
+                    },
+                    DescriptorWrite {
+                        binding: PALETTE_BINDING,
+                        index: 0,
+                        write_info: BufferWriteInfo {
+                            buffer: &palette_buffer,
+                            offset: 0,
+                            len: palette_buffer.size()
+                        }
                     }
                 ]
             )
         }
 
-        (uniform_buffer, voxel_buffer, descriptor_set)
+        (uniform_buffer, [voxel_buffer, palette_buffer], descriptor_set)
+    }
+
+    // after an edit. the buffer may have grown, so it is replaced as a whole
+    fn reupload_octree(&self, octree: &[VoxelData], descriptor_set: &DescriptorSet) -> Buffer<StandartMemoryAllocator> {
+        let staging_buffer = self.create_octree_staging_buffer(octree);
+        let voxel_buffer = self.upload_storage_buffers(&[&staging_buffer]).pop().unwrap();
+
+        unsafe {
+            descriptor_set.update_unchecked(
+                &[
+                    DescriptorWrite {
+                        binding: VOXEL_DATA_BINDING,
+                        index: 0,
+                        write_info: BufferWriteInfo {
+                            buffer: &voxel_buffer,
+                            offset: 0,
+                            len: voxel_buffer.size()
+                        }
+                    },
+                    // the shader only reads the binding of the configured layout, the other one just has to be valid
+                    DescriptorWrite {
+                        binding: COMPACT_NODES_BINDING,
+                        index: 0,
+                        write_info: BufferWriteInfo {
+                            buffer: &voxel_buffer,
+                            offset: 0,
+                            len: voxel_buffer.size()
+                        }
+                    }
+                ]
+            )
+        }
+
+        voxel_buffer
+    }
+
+    // tracer renders radiance to the HDR target, post pass tonemaps it into the render target,
+    // which is blitted to the swapchain. path tracer sums its samples in the accumulation image
+    fn create_storage_image(vk_ctx: &VulkanContext, extent: Extent, format: Format, usage_flags: ImageUsageFlags) -> Image<StandartMemoryAllocator> {
+        vk_ctx.device.create_image(
+            Arc::clone(&vk_ctx.allocator),
+            MemoryTypeProperties::DEVICE_LOCAL,
+            &ImageCreateInfo {
+                usage_flags: ImageUsageFlags::STORAGE | usage_flags,
+                format,
+                image_type: ImageType::Type2D { width: extent.width, height: extent.height },
+                main_owner_queue_family: vk_ctx.queue_family,
+
+                ..Default::default()
+            }
+        ).expect("failed to create storage image")
     }
 
     fn update_movement(&mut self, delta: f32, cam_data: &mut camera::CamBasis) {
@@ -132,12 +262,86 @@ impl Application {
         cam_data.rotate(cam_data.x, rotate_vec.y * MOVE_SPEED_MULTIPLIER * delta);
     }
 
-    pub fn run(mut self) {
+    // input is already updated by update_movement
+    fn update_sun(&self, delta: f32, sun: &mut lighting::Sun) {
+        let azimuth = -self.input_server.get_action_force("sun_left") + self.input_server.get_action_force("sun_right");
+        let elevation = -self.input_server.get_action_force("sun_down") + self.input_server.get_action_force("sun_up");
+
+        sun.rotate(azimuth * SUN_SPEED * delta, elevation * SUN_SPEED * delta);
+    }
+
+    // input is already updated by update_movement
+    fn update_exposure(&mut self, delta: f32) {
+        let change = -self.input_server.get_action_force("exposure_down") + self.input_server.get_action_force("exposure_up");
+
+        self.config.exposure += change * EXPOSURE_SPEED * delta;
+    }
+
+    // true only on the update the action got pressed
+    fn is_action_triggered(&mut self, action: &'static str) -> bool {
+        let pressed = self.input_server.get_action_force(action) > 0.5;
+        let was_pressed = if pressed {
+            !self.held_actions.insert(action)
+        } else {
+            self.held_actions.remove(action)
+        };
+
+        pressed && !was_pressed
+    }
+
+    fn update_toggles(&mut self) {
+        if self.is_action_triggered("cycle_present_mode") {
+            self.config.present_mode = self.config.present_mode.next();
+
+            // present mode is fixed at swapchain creation, so the whole window is recreated
+            let (window_id, windowing_server) = Self::init_windowing_server(&self.vk_ctx, self.config.window_size, self.config.present_mode);
+
+            self.windowing_server = windowing_server;
+            self.window_id = window_id;
+
+            self.windowing_server.window_mut(self.window_id)
+                .unwrap()
+                .show();
+
+            println!("present mode: {}", self.config.present_mode);
+        }
+
+        if self.is_action_triggered("cycle_view_mode") {
+            self.config.view_mode = self.config.view_mode.next();
+
+            println!("view mode: {}", self.config.view_mode);
+        }
+
+        if self.is_action_triggered("cycle_tonemapper") {
+            self.config.tonemapper = self.config.tonemapper.next();
+
+            println!("tonemapper: {}", self.config.tonemapper);
+        }
+    }
+
+    pub fn run(mut self, world: World) {
         let command_pool = self.vk_ctx.compute_queue.create_command_pool().unwrap();
-        let (uniform_buffer, _voxel_data_buffer, descriptor_set) = self.instantiate_resources();
+        let World { octree, palette } = world;
+        // compressing follows every child index, so out of range ones and cycles have to be caught first
+        validate::validate(&octree).expect("loaded octree is invalid");
+        let (mut octree, report) = dag::compress(&octree);
+        let (uniform_buffer, mut storage_buffers, descriptor_set) = self.instantiate_resources(&octree, &palette);
+
+        println!("octree: {report}, {} node layout", self.config.node_layout);
 
         let mut delta = 0.0;
         let mut camera = camera::CamBasis::default();
+        let mut sun = lighting::Sun::from_config(&self.config);
+
+        let mut render_size = self.config.window_size.scaled(self.config.render_scale);
+        let mut render_target = Self::create_storage_image(&self.vk_ctx, render_size, RENDER_TARGET_FORMAT, ImageUsageFlags::TRANSFER_SRC);
+        let mut accumulation = Self::create_storage_image(&self.vk_ctx, render_size, ACCUMULATION_FORMAT, ImageUsageFlags::empty());
+        let mut hdr_target = Self::create_storage_image(&self.vk_ctx, render_size, HDR_TARGET_FORMAT, ImageUsageFlags::empty());
+        // accumulation starts over when anything in here changes
+        let mut accumulated_data = None;
+        let mut accumulated_frames = 0;
+
+        let mut frame_stats = FrameStats::default();
 
         self.windowing_server.window_mut(self.window_id)
             .unwrap()
@@ -147,11 +351,39 @@ impl Application {
 
         'event_loop: loop {
             self.update_movement(delta, &mut camera);
+            self.update_sun(delta, &mut sun);
+            self.update_exposure(delta);
+            self.update_toggles();
+
+            if self.is_action_triggered("pick_voxel") {
+                // ray through the centre of the screen
+                match raycast::raycast(&octree, camera.pos, camera.as_basis_mat() * camera::Vec3::z()) {
+                    Some(hit) => println!(
+                        "picked node {} at {:?}, {} face, {:.3} away",
+                        hit.node_index, hit.coords().as_slice(), hit.face, hit.dist
+                    ),
+                    None => println!("nothing to pick")
+                }
+            }
 
-            unsafe {
-                let mut mapped = uniform_buffer.map::<CameraData>().unwrap();
-
-                mapped[0].write(camera.build_camera_data());
+            if self.is_action_triggered("paint_voxel") {
+                if let Some(hit) = raycast::raycast(&octree, camera.pos, camera.as_basis_mat() * camera::Vec3::z()) {
+                    // the voxel may be shared with others in the DAG
+                    let leaf = dag::unshare(&mut octree, hit.path()) as usize;
+                    let palette_idx = (octree[leaf].pallete_idx + 1) % palette.len() as u32;
+                    octree[leaf].pallete_idx = palette_idx;
+                    // only the aggregates along the path change, but working them out takes the
+                    // coverage of every subtree, so the whole octree is aggregated again
+                    lod::aggregate(&mut octree);
+                    // drops the nodes the copies replaced, which would pile up edit after edit,
+                    // and merges subtrees the edit made equal to others
+                    (octree, _) = dag::compress(&octree);
+
+                    storage_buffers[0] = self.reupload_octree(&octree, &descriptor_set);
+                    accumulated_data = None;
+
+                    println!("painted {:?} with palette entry {palette_idx}", hit.coords().as_slice());
+                }
             }
 
             self.windowing_server.update();
@@ -165,7 +397,11 @@ impl Application {
                 for event in window.events() {
                     match event {
                         WindowEvent::Close => break 'event_loop,
-                        WindowEvent::Resize { .. } => resize_required = true,
+                        WindowEvent::Resize { width, height } => {
+                            // window is recreated with this size on present mode switch too
+                            self.config.window_size = Extent { width, height };
+                            resize_required = true;
+                        },
 
                         _ => {}
                     }
@@ -176,6 +412,14 @@ impl Application {
                 }
             }
 
+            if self.config.window_size.scaled(self.config.render_scale) != render_size {
+                render_size = self.config.window_size.scaled(self.config.render_scale);
+                render_target = Self::create_storage_image(&self.vk_ctx, render_size, RENDER_TARGET_FORMAT, ImageUsageFlags::TRANSFER_SRC);
+                accumulation = Self::create_storage_image(&self.vk_ctx, render_size, ACCUMULATION_FORMAT, ImageUsageFlags::empty());
+                hdr_target = Self::create_storage_image(&self.vk_ctx, render_size, HDR_TARGET_FORMAT, ImageUsageFlags::empty());
+                accumulated_data = None;
+            }
+
             {
                 let swapchain = unsafe { window.swapchain_mut() }.unwrap();
                 let image = loop {
@@ -193,35 +437,97 @@ impl Application {
                     }
                 };
 
+                // fresh accumulation image has undefined contents, so it is reset too
+                let accumulation_layout = if accumulated_data.is_some() { ImageLayout::General } else { ImageLayout::Undefined };
+
+                unsafe {
+                    let mut render_data = RenderData {
+                        camera: camera.build_camera_data(),
+                        light: sun.build_light_data(),
+                        settings: render_settings(&self.config),
+                        post: post_settings(&self.config, !Self::is_srgb_format(image.format()))
+                    };
+                    // post settings only change how the sum is displayed
+                    let scene_data = (render_data.camera, render_data.light, render_data.settings);
+
+                    if accumulated_data != Some(scene_data) {
+                        accumulated_data = Some(scene_data);
+                        accumulated_frames = 0;
+                    }
+
+                    render_data.settings.frame_index = accumulated_frames;
+                    accumulated_frames += 1;
+
+                    let mut mapped = uniform_buffer.map::<u8>().unwrap();
+
+                    gpu_layout::write_mapped(LayoutRules::Std140, &render_data, &mut mapped);
+                }
 
                 unsafe {
-                    let image_view = image.create_image_view_unchecked(
+                    let image_view = render_target.create_image_view_unchecked(
                         &ImageViewCreateInfo {
                             view_type: ImageViewType::Type2D,
-                            format: image.format(),
+                            format: RENDER_TARGET_FORMAT,
                             components: Default::default(),
-                            subresource_range: ImageSubresourceRange {
-                                aspect_mask: ImageAspect::COLOR,
-                                mip_levels: 0..1,
-                                array_layers: 0..1
-                            }
+                            subresource_range: color_subresource_range()
+                        }
+                    ).unwrap();
+                    let accumulation_view = accumulation.create_image_view_unchecked(
+                        &ImageViewCreateInfo {
+                            view_type: ImageViewType::Type2D,
+                            format: ACCUMULATION_FORMAT,
+                            components: Default::default(),
+                            subresource_range: color_subresource_range()
+                        }
+                    ).unwrap();
+                    let hdr_target_view = hdr_target.create_image_view_unchecked(
+                        &ImageViewCreateInfo {
+                            view_type: ImageViewType::Type2D,
+                            format: HDR_TARGET_FORMAT,
+                            components: Default::default(),
+                            subresource_range: color_subresource_range()
                         }
                     ).unwrap();
 
                     descriptor_set.update_unchecked(
                         &[
                             DescriptorWrite {
-                                binding: 2,
+                                binding: RENDER_TARGET_BINDING,
                                 index: 0,
                                 write_info: ImageWriteInfo {
                                     sampler: None,
                                     image_view: &image_view,
                                     image_layout: ImageLayout::General
                                 }
+                            },
+                            DescriptorWrite {
+                                binding: ACCUMULATION_BINDING,
+                                index: 0,
+                                write_info: ImageWriteInfo {
+                                    sampler: None,
+                                    image_view: &accumulation_view,
+                                    image_layout: ImageLayout::General
+                                }
+                            },
+                            DescriptorWrite {
+                                binding: HDR_TARGET_BINDING,
+                                index: 0,
+                                write_info: ImageWriteInfo {
+                                    sampler: None,
+                                    image_view: &hdr_target_view,
+                                    image_layout: ImageLayout::General
+                                }
                             }
                         ]
                     );
 
+                    let blit_region = ImageBlit {
+                        src_subresource: color_subresource_layers(),
+                        src_offsets: [[0, 0, 0], [render_size.width as i32, render_size.height as i32, 1]],
+                        dst_subresource: color_subresource_layers(),
+                        dst_offsets: [[0, 0, 0], [self.config.window_size.width as i32, self.config.window_size.height as i32, 1]]
+                    };
+
                     let command_buffer = command_pool.create_primary_command_buffer(
                         CommandBufferUsageFlags::ONE_TIME_SUBMIT
                     ).unwrap()
@@ -237,43 +543,147 @@ impl Application {
                                     src_access_mask: AccessFlags::empty(),
                                     dst_access_mask: AccessFlags::SHADER_WRITE,
 
+                                    // previous contents are not needed
                                     old_layout: ImageLayout::Undefined,
                                     new_layout: ImageLayout::General,
                                     src_queue_family_index: u32::MAX,
                                     dst_queue_family_index: u32::MAX,
-                                    image: &image,
-                                    subresource_range: ImageSubresourceRange {
-                                        aspect_mask: ImageAspect::COLOR,
-                                        mip_levels: 0..1,
-                                        array_layers: 0..1
-                                    }
+                                    image: &render_target,
+                                    subresource_range: color_subresource_range()
+                                },
+                                ImageMemoryBarrier {
+                                    src_access_mask: AccessFlags::empty(),
+                                    dst_access_mask: AccessFlags::SHADER_WRITE,
+
+                                    old_layout: ImageLayout::Undefined,
+                                    new_layout: ImageLayout::General,
+                                    src_queue_family_index: u32::MAX,
+                                    dst_queue_family_index: u32::MAX,
+                                    image: &hdr_target,
+                                    subresource_range: color_subresource_range()
                                 }
                             ],
                             &[]
                         )
-                        .cmd_dispatch_unchecked(600, 400, 1)
                         .cmd_pipeline_barrier_unchecked::<_, HollowDeviceMemoryAllocator>(
                             PipelineStageFlags::COMPUTE_SHADER,
-                            PipelineStageFlags::BOTTOM_OF_PIPE,
+                            PipelineStageFlags::COMPUTE_SHADER,
                             PipelineBarrierDependencyFlags::empty(),
                             &[],
                             &[
+                                // previous frame's sum is read back
                                 ImageMemoryBarrier {
                                     src_access_mask: AccessFlags::SHADER_WRITE,
-                                    dst_access_mask: AccessFlags::empty(),
+                                    dst_access_mask: AccessFlags::SHADER_READ | AccessFlags::SHADER_WRITE,
+
+                                    old_layout: accumulation_layout,
+                                    new_layout: ImageLayout::General,
+                                    src_queue_family_index: u32::MAX,
+                                    dst_queue_family_index: u32::MAX,
+                                    image: &accumulation,
+                                    subresource_range: color_subresource_range()
+                                }
+                            ],
+                            &[]
+                        )
+                        .cmd_dispatch_unchecked(
+                            render_size.width.div_ceil(self.config.tile_size.width),
+                            render_size.height.div_ceil(self.config.tile_size.height),
+                            1
+                        )
+                        .cmd_pipeline_barrier_unchecked::<_, HollowDeviceMemoryAllocator>(
+                            PipelineStageFlags::COMPUTE_SHADER,
+                            PipelineStageFlags::COMPUTE_SHADER,
+                            PipelineBarrierDependencyFlags::empty(),
+                            &[],
+                            &[
+                                ImageMemoryBarrier {
+                                    src_access_mask: AccessFlags::SHADER_WRITE,
+                                    dst_access_mask: AccessFlags::SHADER_READ,
+
+                                    old_layout: ImageLayout::General,
+                                    new_layout: ImageLayout::General,
+                                    src_queue_family_index: u32::MAX,
+                                    dst_queue_family_index: u32::MAX,
+                                    image: &hdr_target,
+                                    subresource_range: color_subresource_range()
+                                }
+                            ],
+                            &[]
+                        )
+                        .cmd_bind_compute_pipeline_unchecked(&self.post_pipeline)
+                        .cmd_dispatch_unchecked(
+                            render_size.width.div_ceil(self.config.tile_size.width),
+                            render_size.height.div_ceil(self.config.tile_size.height),
+                            1
+                        )
+                        .cmd_pipeline_barrier_unchecked::<_, HollowDeviceMemoryAllocator>(
+                            PipelineStageFlags::COMPUTE_SHADER,
+                            PipelineStageFlags::TRANSFER,
+                            PipelineBarrierDependencyFlags::empty(),
+                            &[],
+                            &[
+                                ImageMemoryBarrier {
+                                    src_access_mask: AccessFlags::SHADER_WRITE,
+                                    dst_access_mask: AccessFlags::TRANSFER_READ,
 
                                     old_layout: ImageLayout::General,
+                                    new_layout: ImageLayout::TransferSrcOptimal,
+                                    src_queue_family_index: u32::MAX,
+                                    dst_queue_family_index: u32::MAX,
+                                    image: &render_target,
+                                    subresource_range: color_subresource_range()
+                                }
+                            ],
+                            &[]
+                        )
+                        .cmd_pipeline_barrier_unchecked::<_, HollowDeviceMemoryAllocator>(
+                            PipelineStageFlags::TOP_OF_PIPE,
+                            PipelineStageFlags::TRANSFER,
+                            PipelineBarrierDependencyFlags::empty(),
+                            &[],
+                            &[
+                                ImageMemoryBarrier {
+                                    src_access_mask: AccessFlags::empty(),
+                                    dst_access_mask: AccessFlags::TRANSFER_WRITE,
+
+                                    old_layout: ImageLayout::Undefined,
+                                    new_layout: ImageLayout::TransferDstOptimal,
+                                    src_queue_family_index: u32::MAX,
+                                    dst_queue_family_index: u32::MAX,
+                                    image: &image,
+                                    subresource_range: color_subresource_range()
+                                }
+                            ],
+                            &[]
+                        )
+                        // scales and converts to the swapchain format at once
+                        .cmd_blit_image_unchecked(
+                            &render_target,
+                            ImageLayout::TransferSrcOptimal,
+                            &image,
+                            ImageLayout::TransferDstOptimal,
+                            core::slice::from_ref(&blit_region),
+                            Filter::Linear
+                        )
+                        .cmd_pipeline_barrier_unchecked::<_, HollowDeviceMemoryAllocator>(
+                            PipelineStageFlags::TRANSFER,
+                            PipelineStageFlags::BOTTOM_OF_PIPE,
+                            PipelineBarrierDependencyFlags::empty(),
+                            &[],
+                            &[
+                                ImageMemoryBarrier {
+                                    src_access_mask: AccessFlags::TRANSFER_WRITE,
+                                    dst_access_mask: AccessFlags::empty(),
+
+                                    old_layout: ImageLayout::TransferDstOptimal,
                                     new_layout: ImageLayout::PresentSrc,
 
                                     src_queue_family_index: u32::MAX,
                                     dst_queue_family_index: u32::MAX,
 
                                     image: &image,
-                                    subresource_range: ImageSubresourceRange {
-                                        aspect_mask: ImageAspect::COLOR,
-                                        mip_levels: 0..1,
-                                        array_layers: 0..1
-                                    }
+                                    subresource_range: color_subresource_range()
                                 }
                             ],
                             &[]
@@ -315,6 +725,113 @@ impl Application {
                 delta = current_time.duration_since(time).as_secs_f32();
                 time = current_time;
             }
+
+            if let Some(duration) = self.config.benchmark {
+                frame_stats.push(delta);
+
+                if frame_stats.total_time() >= duration {
+                    println!(
+                        "{}x{} render target, {}x{} tiles: {frame_stats}",
+                        render_size.width, render_size.height,
+                        self.config.tile_size.width, self.config.tile_size.height
+                    );
+
+                    break 'event_loop;
+                }
+            }
         }
     }
+}
+
+
+fn render_settings(config: &Config) -> RenderSettings {
+    RenderSettings {
+        view_mode: config.view_mode as u32,
+        ao_samples: config.ao_samples,
+        ao_radius: config.ao_radius,
+        frame_index: 0,
+        max_bounces: config.max_bounces,
+        antialiasing: config.antialiasing as u32,
+        jitter: config.jitter as u32,
+        lod_pixels: config.lod_pixels
+    }
+}
+
+fn post_settings(config: &Config, encode_srgb: bool) -> PostSettings {
+    PostSettings {
+        exposure: config.exposure.exp2(),
+        tonemapper: config.tonemapper as u32,
+        encode_srgb: encode_srgb as u32
+    }
+}
+
+/// Octree to render and the palette its leaves index into
+pub struct World {
+    octree: Vec<VoxelData>,
+    palette: Vec<PaletteEntry>
+}
+
+/// Imports the heightmap, the mesh, the point cloud or the volume if there is one, generates the scene otherwise
+pub fn load_world(config: &Config) -> Result<World, import::ImportError> {
+    let (octree, palette) = if let Some(path) = &config.heightmap {
+        import::import_heightmap(path, config.color_map.as_deref(), config.height_scale, config.palette_colors)?
+    } else if let Some(path) = &config.mesh {
+        import::import_mesh(path, config.mesh_depth, config.mesh_fill, config.palette_colors)?
+    } else if let Some(path) = &config.points {
+        import::import_points(path, config.point_depth, config.palette_colors)?
+    } else if let Some(path) = &config.volume {
+        import::import_volume(path, config.volume_size, config.volume_bits, &config.transfer)?
+    } else {
+        match config.scene {
+            Scene::Fractal => (voxel_data_generator::generate_tree(GENERATED_TREE_LAYERS), voxel_data_generator::PALETTE.to_vec()),
+            Scene::Terrain => (terrain::generate_terrain(&config.terrain), terrain::PALETTE.to_vec())
+        }
+    };
+
+    Ok(World { octree, palette })
+}
+
+/// Prints statistics of the octree the viewer would show
+pub fn inspect_octree(world: &World) -> Result<(), Vec<validate::ValidationError>> {
+    // validates before anything else follows the child indices
+    let stats = inspect::inspect(&world.octree)?;
+    let (_, report) = dag::compress(&world.octree);
+
+    print!("{stats}");
+    println!("\nDAG compression: {report}");
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum CpuReferenceError {
+    Invalid(Vec<validate::ValidationError>),
+    Io(io::Error)
+}
+
+/// Renders frame `CPU_REFERENCE_FRAMES - 1` on the CPU, averaged with the ones before it when the
+/// view mode accumulates. Same scene and camera as `Application::run`
+pub fn render_cpu_reference(config: &Config, world: &World, path: &Path) -> Result<(), CpuReferenceError> {
+    // as in `Application::run`, a broken octree would make compressing panic
+    validate::validate(&world.octree).map_err(CpuReferenceError::Invalid)?;
+
+    let (octree, _) = dag::compress(&world.octree);
+    let scene = cpu_reference::Scene {
+        octree: &octree,
+        palette: &world.palette,
+        light: lighting::Sun::from_config(config).build_light_data(),
+        settings: RenderSettings {
+            frame_index: CPU_REFERENCE_FRAMES - 1,
+
+            ..render_settings(config)
+        },
+        // PPM is sRGB, so encode like with an UNORM swapchain
+        post: post_settings(config, true)
+    };
+    let camera = camera::CamBasis::default().build_camera_data();
+    let size = config.window_size.scaled(config.render_scale);
+
+    let pixels = cpu_reference::render(&scene, &camera, size);
+
+    cpu_reference::write_ppm(path, size, &pixels).map_err(CpuReferenceError::Io)
 }
\ No newline at end of file
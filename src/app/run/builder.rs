@@ -0,0 +1,131 @@
+//! Shared parts of the octree builders. Top down ones decide what a region of their voxel grid holds,
+//! split the regions they can't decide and merge children that turn out uniform into a single leaf.
+//! The bottom up one takes voxels in Morton order and closes every node as soon as it is complete
+
+use std::{array, iter};
+
+use super::{camera::UVec3, gpu_shared_data::VoxelData, lod};
+
+pub enum Built {
+    Empty,
+    /// Uniform, stored as a single leaf
+    Solid(u32),
+    /// Children are pushed already, the node itself is pushed by its parent
+    Node(VoxelData)
+}
+
+/// -X -Y -Z corner of a child, in voxels. Same slot bits as the shader: bit 0 is x, bit 1 is z and bit 2 is y
+pub fn child_min(min: UVec3, half_size: u32, slot: usize) -> UVec3 {
+    let slot = slot as u32;
+
+    min + UVec3::new(slot & 1, (slot >> 2) & 1, (slot >> 1) & 1) * half_size
+}
+
+/// Merges uniform children, otherwise pushes them and returns their parent
+pub fn combine(nodes: &mut Vec<VoxelData>, children: [Built; 8]) -> Built {
+    if children.iter().all(| child | matches!(child, Built::Empty)) {
+        return Built::Empty;
+    }
+
+    if let Built::Solid(first) = children[0] {
+        if children.iter().all(| child | matches!(child, Built::Solid(palette) if *palette == first)) {
+            return Built::Solid(first);
+        }
+    }
+
+    let mut node = VoxelData::leaf(0);
+
+    for (slot, child) in children.into_iter().enumerate() {
+        let child = match child {
+            Built::Empty => continue,
+            Built::Solid(palette) => VoxelData::leaf(palette),
+            Built::Node(child) => child
+        };
+
+        nodes.push(child);
+        node.child_indicies[slot] = (nodes.len() - 1) as u32;
+    }
+
+    Built::Node(node)
+}
+
+/// Octree of the root `build_root` returns after pushing the nodes below it. Index 0 is kept free
+/// for the root, which is filled in last, and the interior nodes are aggregated
+pub fn build(build_root: impl FnOnce(&mut Vec<VoxelData>) -> Built) -> Vec<VoxelData> {
+    let mut nodes = vec![VoxelData::leaf(0)];
+    let root = build_root(&mut nodes);
+
+    nodes[0] = match root {
+        Built::Node(root) => root,
+        // a solid cube, the walk never hits the root itself
+        Built::Solid(palette) => {
+            let mut root = VoxelData::leaf(palette);
+
+            for child in &mut root.child_indicies {
+                nodes.push(VoxelData::leaf(palette));
+                *child = (nodes.len() - 1) as u32;
+            }
+
+            root
+        },
+        Built::Empty => VoxelData::leaf(0)
+    };
+
+    lod::aggregate(&mut nodes);
+
+    nodes
+}
+
+/// Interleaves the coordinates so that every three bits are a child slot, the root's slot highest.
+/// Sorted codes are in the order of a depth first walk
+pub fn morton_code(voxel: UVec3, depth: u32) -> u64 {
+    (0..depth).fold(0, | code, bit | {
+        let slot = (voxel.x >> bit & 1) | (voxel.z >> bit & 1) << 1 | (voxel.y >> bit & 1) << 2;
+
+        code | (slot as u64) << (bit * 3)
+    })
+}
+
+/// Builds the octree from voxels with distinct Morton codes in ascending order, in one pass with
+/// only the nodes along the path to the last voxel open. Interior nodes are aggregated. Panics on
+/// codes out of order or repeated, which would build a wrong octree
+pub fn build_sorted(depth: u32, voxels: impl IntoIterator<Item = (u64, u32)>) -> Vec<VoxelData> {
+    let depth = depth as usize;
+    let slot = | code: u64, layer: usize | (code >> ((depth - 1 - layer) * 3) & 7) as usize;
+
+    // children of the open node on every layer, the root is layer 0
+    let mut open: Vec<[Built; 8]> = iter::repeat_with(|| array::from_fn(| _ | Built::Empty)).take(depth).collect();
+    let mut previous: Option<u64> = None;
+
+    // closes the nodes on the layers below `layer`, the previous voxel was the last one in them
+    let close = | nodes: &mut Vec<VoxelData>, open: &mut [[Built; 8]], previous: u64, layer: usize | {
+        for closed in (layer + 1..depth).rev() {
+            let children = std::mem::replace(&mut open[closed], array::from_fn(| _ | Built::Empty));
+
+            open[closed - 1][slot(previous, closed - 1)] = combine(nodes, children);
+        }
+    };
+
+    build(| nodes | {
+        for (code, palette_idx) in voxels {
+            if let Some(previous) = previous {
+                assert!(previous < code, "voxels are not sorted or not distinct");
+
+                // first layer whose child slots differ, the highest differing bit tells it
+                let highest_bit = 63 - (previous ^ code).leading_zeros() as usize;
+                close(nodes, &mut open, previous, depth - 1 - highest_bit / 3);
+            }
+
+            open[depth - 1][slot(code, depth - 1)] = Built::Solid(palette_idx);
+            previous = Some(code);
+        }
+
+        if let Some(previous) = previous {
+            close(nodes, &mut open, previous, 0);
+        }
+
+        let children = std::mem::replace(&mut open[0], array::from_fn(| _ | Built::Empty));
+
+        combine(nodes, children)
+    })
+}
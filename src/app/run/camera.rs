@@ -7,6 +7,7 @@ pub type Mat3 = nalgebra::Matrix3<f32>;
 pub type Mat4 = nalgebra::Matrix4<f32>;
 pub type Vec3 = nalgebra::Vector3<f32>;
 pub type Vec2 = nalgebra::Vector2<f32>;
+pub type UVec3 = nalgebra::Vector3<u32>;
 
 pub struct CamBasis {
     pub x: Vec3,
@@ -62,15 +63,6 @@ impl CamBasis {
         ).transpose()
     }
 
-    pub fn as_4d_basis_mat(&self) -> Mat4 {
-        Mat4::new(
-            self.x.x, self.x.y, self.x.z, 0.0,
-            self.y.x, self.y.y, self.y.z, 0.0,
-            self.z.x, self.z.y, self.z.z, 0.0,
-            0.0, 0.0, 0.0, 1.0
-        ).transpose()
-    }
-
     pub fn build_look_at_matrix(&self) -> Mat4 {
         Mat4::new(
             self.x.x, self.x.y, self.x.z, 0.0,
@@ -84,8 +76,8 @@ impl CamBasis {
     // the only new addition
     pub fn build_camera_data(&self) -> CameraData {
         CameraData {
-            pos: self.pos.into(),
-            basis: self.as_4d_basis_mat()
+            pos: self.pos,
+            basis: self.as_basis_mat()
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,178 @@
+//! Compact octree encoding: three words per node instead of ten. A node keeps masks of its
+//! occupied slots and of its leaf children, the index of its first child and its coverage.
+//! Children follow it contiguously, the nodes of the interior ones first, then the palette indices
+//! of the leaves packed six to a node, so leaves take a sixth of a node each
+
+use std::collections::HashMap;
+
+use super::gpu_shared_data::{CompactNode, VoxelData, EMPTY_NODE};
+
+const LEAF_SHIFT: u32 = 8;
+const PALETTE_SHIFT: u32 = 16;
+// palette indices have to fit the upper half of `CompactNode::masks`
+const MAX_PALETTE_INDEX: u32 = u16::MAX as u32;
+const LEAVES_PER_NODE: usize = 6;
+// the shader refers to a leaf child by its node times six plus its place in the node, with the top bit set
+const MAX_NODES: usize = (1 << 31) / LEAVES_PER_NODE;
+
+struct Converter<'a> {
+    octree: &'a [VoxelData],
+    nodes: Vec<CompactNode>,
+    // shared input nodes share their block of children too, so a DAG stays a DAG
+    child_blocks: HashMap<u32, u32>
+}
+
+impl Converter<'_> {
+    fn convert(&mut self, index: u32) -> CompactNode {
+        let node = &self.octree[index as usize];
+        assert!(node.pallete_idx <= MAX_PALETTE_INDEX, "palette index {} of node {index} does not fit the compact layout", node.pallete_idx);
+
+        let mut valid = 0;
+        let mut leaf = 0;
+        let mut interior_children = Vec::new();
+        let mut leaf_palettes = Vec::new();
+
+        for (slot, &child) in node.child_indicies.iter().enumerate().filter(| (_, child) | **child != EMPTY_NODE) {
+            let child_node = &self.octree[child as usize];
+            valid |= 1 << slot;
+
+            if child_node.is_leaf() {
+                assert!(child_node.pallete_idx <= MAX_PALETTE_INDEX, "palette index {} of node {child} does not fit the compact layout", child_node.pallete_idx);
+
+                leaf |= 1 << slot;
+                leaf_palettes.push(child_node.pallete_idx);
+            } else {
+                interior_children.push(child);
+            }
+        }
+
+        let first_child = match self.child_blocks.get(&index) {
+            Some(&first_child) => first_child,
+            None if valid == 0 => EMPTY_NODE,
+            None => {
+                let first_child = self.nodes.len();
+                let leaves_start = first_child + interior_children.len();
+
+                // reserve the whole block before descending, grandchildren go after it
+                self.nodes.resize(leaves_start + leaf_palettes.len().div_ceil(LEAVES_PER_NODE), CompactNode { masks: 0, first_child: 0, coverage: 0 });
+                self.child_blocks.insert(index, first_child as u32);
+                assert!(self.nodes.len() <= MAX_NODES, "octree has too many nodes for the compact layout");
+
+                // two palette indices per word, in the order of the words of a node
+                for (word, pair) in leaf_palettes.chunks(2).enumerate() {
+                    let packed = pair[0] | pair.get(1).map_or(0, | palette | palette << 16);
+                    let node = &mut self.nodes[leaves_start + word / 3];
+
+                    match word % 3 {
+                        0 => node.masks = packed,
+                        1 => node.first_child = packed,
+                        _ => node.coverage = packed
+                    }
+                }
+
+                for (offset, child) in interior_children.into_iter().enumerate() {
+                    self.nodes[first_child + offset] = self.convert(child);
+                }
+
+                first_child as u32
+            }
+        };
+
+        CompactNode {
+            masks: valid | leaf << LEAF_SHIFT | node.pallete_idx << PALETTE_SHIFT,
+            first_child,
+            coverage: node.coverage
+        }
+    }
+}
+
+/// Re-encodes an octree or DAG rooted at index 0, the root stays there. Children of a node
+/// which is shared in the input are stored once
+pub fn from_voxel_data(octree: &[VoxelData]) -> Vec<CompactNode> {
+    let mut converter = Converter {
+        octree,
+        nodes: vec![CompactNode { masks: 0, first_child: EMPTY_NODE, coverage: 0 }],
+        child_blocks: HashMap::new()
+    };
+
+    converter.nodes[0] = converter.convert(0);
+
+    converter.nodes
+}
+
+
+
+#[test]
+fn test_compact_layout() {
+    use super::{dag, gpu_shared_data::FULL_COVERAGE, voxel_data_generator::generate_tree};
+
+    const LEAF_CHILD: u32 = 1 << 31;
+
+    // same lookups as child_node and palette_index in the shader
+    fn child_node(compact: &[CompactNode], index: u32, slot: u32) -> u32 {
+        let masks = compact[index as usize].masks;
+        let bit = 1 << slot;
+        let leaves = masks >> LEAF_SHIFT & 0xff;
+        let interior = masks & 0xff & !leaves;
+        let first_child = compact[index as usize].first_child;
+
+        match (masks & bit != 0, leaves & bit != 0) {
+            (false, _) => EMPTY_NODE,
+            (true, true) => LEAF_CHILD | ((first_child + interior.count_ones()) * 6 + (leaves & (bit - 1)).count_ones()),
+            (true, false) => first_child + (interior & (bit - 1)).count_ones()
+        }
+    }
+
+    fn palette_index(compact: &[CompactNode], index: u32) -> u32 {
+        if index & LEAF_CHILD == 0 {
+            return compact[index as usize].masks >> PALETTE_SHIFT;
+        }
+
+        let sixth = index & !LEAF_CHILD;
+        let node = &compact[sixth as usize / 6];
+        let word = [node.masks, node.first_child, node.coverage][sixth as usize % 6 / 2];
+
+        word >> (sixth % 2 * 16) & 0xffff
+    }
+
+    fn assert_same_subtree(octree: &[VoxelData], index: u32, compact: &[CompactNode], compact_index: u32) {
+        let node = &octree[index as usize];
+        assert_eq!(palette_index(compact, compact_index), node.pallete_idx);
+        assert!(compact_index & LEAF_CHILD != 0 || compact[compact_index as usize].coverage == node.coverage);
+
+        // leaf children are not stored as nodes at all
+        if compact_index & LEAF_CHILD != 0 {
+            assert!(node.is_leaf());
+            return;
+        }
+
+        for (slot, &child) in node.child_indicies.iter().enumerate() {
+            let compact_child = child_node(compact, compact_index, slot as u32);
+            assert_eq!(compact_child != EMPTY_NODE, child != EMPTY_NODE);
+
+            if child != EMPTY_NODE {
+                assert_eq!(compact_child & LEAF_CHILD != 0, octree[child as usize].is_leaf());
+
+                assert_same_subtree(octree, child, compact, compact_child);
+            }
+        }
+    }
+
+    let octree = generate_tree(4);
+    let compact = from_voxel_data(&octree);
+    let leaves = octree.iter().filter(| node | node.is_leaf()).count();
+
+    // a sixth of a node per leaf, rounded up per block
+    assert!(compact.len() < octree.len() - leaves + leaves.div_ceil(3));
+    assert_same_subtree(&octree, 0, &compact, 0);
+
+    // children of shared nodes are converted once, only the shared nodes themselves are repeated
+    let (dag, _) = dag::compress(&octree);
+    let compact_dag = from_voxel_data(&dag);
+
+    assert!(compact_dag.len() < compact.len());
+    assert_same_subtree(&dag, 0, &compact_dag, 0);
+
+    // a root without children is a leaf itself
+    assert_eq!(from_voxel_data(&[VoxelData::leaf(7)]), [CompactNode { masks: 7 << PALETTE_SHIFT, first_child: EMPTY_NODE, coverage: FULL_COVERAGE }]);
+}
@@ -0,0 +1,693 @@
+//! CPU port of rendering_shader.comp, on top of `raycast::trace`.
+//! Slow, but every intermediate value can be printed, so it is the first place to look
+//! when the GPU output looks wrong. Keep it in sync with the shader!
+
+use std::{f32::consts::PI, fs::File, io::{self, BufWriter, Write}, path::Path};
+
+use crate::app::config::{Antialiasing, Extent, Tonemapper, ViewMode};
+
+use super::{camera::Vec3, gpu_shared_data::{CameraData, LightData, PaletteEntry, PostSettings, RenderSettings, VoxelData, MATERIAL_GLASS, MATERIAL_METAL}, raycast::{hash, raycast, trace, unit_float, Face, RayHit, TraceResult, MAX_WALK_DEPTH}};
+
+const HEATMAP_MAX_STEPS: f32 = 128.0;
+const SECONDARY_RAY_BIAS: f32 = 0.01;
+const MAX_INTERNAL_REFLECTIONS: u32 = 4;
+
+const SKY_ZENITH: Vec3 = Vec3::new(0.15, 0.3, 0.65);
+const SKY_HORIZON: Vec3 = Vec3::new(0.55, 0.65, 0.8);
+const SKY_GROUND: Vec3 = Vec3::new(0.12, 0.11, 0.1);
+const SKY_NIGHT: Vec3 = Vec3::new(0.004, 0.006, 0.015);
+const SUN_DISC_COS: f32 = 0.9995;
+const SUN_DISC_INTENSITY: f32 = 10.0;
+
+const GRID_2X2_OFFSETS: [[f32; 2]; 4] = [[-0.25, -0.25], [0.25, -0.25], [-0.25, 0.25], [0.25, 0.25]];
+const RGSS_OFFSETS: [[f32; 2]; 4] = [[0.125, -0.375], [0.375, 0.125], [-0.125, 0.375], [-0.375, -0.125]];
+
+/// Everything the shader reads from its descriptors, except the camera
+pub struct Scene<'a> {
+    pub octree: &'a [VoxelData],
+    pub palette: &'a [PaletteEntry],
+    pub light: LightData,
+    pub settings: RenderSettings,
+    pub post: PostSettings
+}
+
+fn heatmap(t: f32) -> Vec3 {
+    let t = t.clamp(0.0, 1.0);
+
+    Vec3::new(2.0 * t - 1.0, 1.0 - (2.0 * t - 1.0).abs(), 1.0 - 2.0 * t)
+        .map(| c | c.clamp(0.0, 1.0))
+}
+
+fn hash_color(idx: u32) -> Vec3 {
+    let idx = hash(idx);
+
+    Vec3::new((idx & 0xff) as f32, ((idx >> 8) & 0xff) as f32, ((idx >> 16) & 0xff) as f32) / 255.0
+}
+
+fn linear_to_srgb(color: Vec3) -> Vec3 {
+    color.map(| c | {
+        let c = c.clamp(0.0, 1.0);
+
+        if c > 0.0031308 { 1.055 * c.powf(1.0 / 2.4) - 0.055 } else { c * 12.92 }
+    })
+}
+
+fn aces(color: Vec3) -> Vec3 {
+    color.map(| c | ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0))
+}
+
+fn tonemap(color: Vec3, tonemapper: Tonemapper) -> Vec3 {
+    match tonemapper {
+        Tonemapper::None => color.map(| c | c.clamp(0.0, 1.0)),
+        Tonemapper::Reinhard => color.map(| c | c / (1.0 + c)),
+        Tonemapper::Aces => aces(color)
+    }
+}
+
+fn sample_hemisphere(normal: Vec3, u: [f32; 2]) -> Vec3 {
+    let up = if normal.y.abs() < 0.99 { Vec3::y() } else { Vec3::x() };
+    let tangent = up.cross(&normal).normalize();
+    let bitangent = normal.cross(&tangent);
+
+    let r = u[0].sqrt();
+    let (sin_phi, cos_phi) = (2.0 * PI * u[1]).sin_cos();
+
+    tangent * (r * cos_phi) + bitangent * (r * sin_phi) + normal * (1.0 - u[0]).sqrt()
+}
+
+fn ambient_occlusion(scene: &Scene, pos: Vec3, normal: Vec3, pixel: [u32; 2]) -> f32 {
+    let samples = scene.settings.ao_samples;
+
+    if samples == 0 {
+        return 1.0;
+    }
+
+    let seed = hash(pixel[0] ^ hash(pixel[1]));
+    let noise = [unit_float(hash(seed)), unit_float(hash(seed.wrapping_add(1)))];
+
+    let escaped = (0..samples)
+        .map(| i | [
+            (noise[0] + (i as f32 + 0.5) / samples as f32).fract(),
+            (noise[1] + i as f32 * 0.618034).fract()
+        ])
+        .filter(| &u | raycast(scene.octree, pos, sample_hemisphere(normal, u))
+            .is_none_or(| occluder | occluder.dist > scene.settings.ao_radius)
+        )
+        .count();
+
+    escaped as f32 / samples as f32
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn sky_color(light: &LightData, direction: Vec3, with_sun_disc: bool) -> Vec3 {
+    let up = -direction.y;
+    let day = smoothstep(-0.1, 0.15, -light.sun_direction.y);
+
+    let color = if up > 0.0 {
+        SKY_HORIZON.lerp(&SKY_ZENITH, up.sqrt())
+    } else {
+        SKY_HORIZON.lerp(&SKY_GROUND, (-up).sqrt())
+    };
+    let mut color = SKY_NIGHT.lerp(&color, day);
+
+    let cos_sun = direction.dot(&light.sun_direction);
+    color += light.sun_color * (0.25 * cos_sun.max(0.0).powf(32.0) * day);
+
+    if with_sun_disc && cos_sun > SUN_DISC_COS {
+        color += light.sun_color * SUN_DISC_INTENSITY;
+    }
+
+    color
+}
+
+fn apply_fog(light: &LightData, color: Vec3, direction: Vec3, dist: f32) -> Vec3 {
+    let transmittance = (-light.fog_density * dist).exp();
+
+    sky_color(light, direction, false).lerp(&color, transmittance)
+}
+
+fn material(scene: &Scene, hit: &RayHit) -> PaletteEntry {
+    scene.palette[scene.octree[hit.node_index as usize].pallete_idx as usize]
+}
+
+fn shade_hit(scene: &Scene, origin: Vec3, direction: Vec3, hit: &RayHit, pixel: [u32; 2]) -> Vec3 {
+    let material = material(scene, hit);
+    let surface_pos = origin + direction * hit.dist + hit.normal() * SECONDARY_RAY_BIAS;
+
+    let mut n_dot_l = hit.normal().dot(&scene.light.sun_direction).max(0.0);
+
+    if n_dot_l > 0.0 && raycast(scene.octree, surface_pos, scene.light.sun_direction).is_some() {
+        n_dot_l = 0.0;
+    }
+
+    let ao = ambient_occlusion(scene, surface_pos, hit.normal(), pixel);
+    let light = scene.light.ambient_color * ao + scene.light.sun_color * n_dot_l + Vec3::repeat(material.emission);
+
+    material.color.component_mul(&light)
+}
+
+// same as in GLSL, normal faces against the ray
+fn reflect(direction: Vec3, normal: Vec3) -> Vec3 {
+    direction - normal * 2.0 * normal.dot(&direction)
+}
+
+// same as in GLSL, zero on total internal reflection
+fn refract(direction: Vec3, normal: Vec3, eta: f32) -> Vec3 {
+    let cos = normal.dot(&direction);
+    let k = 1.0 - eta * eta * (1.0 - cos * cos);
+
+    if k < 0.0 { Vec3::zeros() } else { direction * eta - normal * (eta * cos + k.sqrt()) }
+}
+
+fn fresnel(cos_theta: f32, ior: f32) -> f32 {
+    let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+
+    r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
+}
+
+fn pass_through_glass(hit_pos: Vec3, direction: &mut Vec3, hit: &RayHit, ior: f32) -> Vec3 {
+    let voxel_min = hit.min_corner();
+    let voxel_max = voxel_min + Vec3::repeat(hit.size());
+
+    let mut pos = hit_pos;
+    let mut inner_dir = refract(*direction, hit.normal(), 1.0 / ior);
+
+    for i in 0.. {
+        let t = Vec3::from_fn(| axis, _ | {
+            let wall_dist = if inner_dir[axis] > 0.0 { voxel_max[axis] - pos[axis] } else { pos[axis] - voxel_min[axis] };
+
+            wall_dist.max(0.0) / inner_dir[axis].abs().max(1e-6)
+        });
+        let axis = if t.x < t.y { if t.x < t.z { 0 } else { 2 } } else if t.y < t.z { 1 } else { 2 };
+
+        pos += inner_dir * t[axis];
+        let exit_normal = Face::ALL[if inner_dir[axis] > 0.0 { axis + 3 } else { axis }].normal();
+        let out_dir = refract(inner_dir, -exit_normal, ior);
+
+        if out_dir != Vec3::zeros() || i + 1 == MAX_INTERNAL_REFLECTIONS {
+            *direction = if out_dir != Vec3::zeros() { out_dir } else { inner_dir };
+
+            return pos + exit_normal * SECONDARY_RAY_BIAS;
+        }
+
+        inner_dir = reflect(inner_dir, -exit_normal);
+    }
+
+    unreachable!()
+}
+
+fn shade_through_specular(scene: &Scene, mut origin: Vec3, mut direction: Vec3, mut hit: Option<RayHit>, pixel: [u32; 2]) -> Vec3 {
+    let mut throughput = Vec3::repeat(1.0);
+
+    for _ in 0..scene.settings.max_bounces {
+        let Some(current) = hit else { break };
+
+        let material = material(scene, &current);
+        let hit_pos = origin + direction * current.dist;
+
+        match material.material {
+            MATERIAL_METAL => {
+                origin = hit_pos + current.normal() * SECONDARY_RAY_BIAS;
+                direction = reflect(direction, current.normal());
+            },
+            MATERIAL_GLASS => origin = pass_through_glass(hit_pos, &mut direction, &current, material.ior),
+            _ => break
+        }
+
+        throughput.component_mul_assign(&material.color);
+        hit = raycast(scene.octree, origin, direction);
+    }
+
+    let color = match hit {
+        Some(hit) => shade_hit(scene, origin, direction, &hit, pixel),
+        None => sky_color(&scene.light, direction, true)
+    };
+
+    throughput.component_mul(&color)
+}
+
+
+struct Rng(u32);
+
+impl Rng {
+    fn new(pixel: [u32; 2], frame_index: u32) -> Self {
+        Self(hash(pixel[0] ^ hash(pixel[1] ^ hash(frame_index))))
+    }
+
+    fn next_float(&mut self) -> f32 {
+        self.0 = hash(self.0);
+
+        unit_float(self.0)
+    }
+}
+
+fn trace_path(scene: &Scene, mut origin: Vec3, mut direction: Vec3, mut hit: Option<RayHit>, rng: &mut Rng) -> Vec3 {
+    let mut radiance = Vec3::zeros();
+    let mut throughput = Vec3::repeat(1.0);
+    let mut is_specular = true;
+
+    for bounce in 0.. {
+        let Some(current) = hit else {
+            radiance += throughput.component_mul(&sky_color(&scene.light, direction, is_specular));
+            break;
+        };
+
+        let material = material(scene, &current);
+        let normal = current.normal();
+        let hit_pos = origin + direction * current.dist;
+        let surface_pos = hit_pos + normal * SECONDARY_RAY_BIAS;
+
+        radiance += throughput.component_mul(&material.color) * material.emission;
+
+        if [MATERIAL_METAL, MATERIAL_GLASS].contains(&material.material) {
+            if bounce == scene.settings.max_bounces {
+                break;
+            }
+
+            is_specular = true;
+
+            if material.material == MATERIAL_METAL {
+                let blur = sample_hemisphere(normal, [rng.next_float(), rng.next_float()]);
+
+                origin = surface_pos;
+                direction = (reflect(direction, normal) + blur * material.roughness).normalize();
+                throughput.component_mul_assign(&material.color);
+            } else if rng.next_float() < fresnel(-direction.dot(&normal), material.ior) {
+                origin = surface_pos;
+                direction = reflect(direction, normal);
+            } else {
+                origin = pass_through_glass(hit_pos, &mut direction, &current, material.ior);
+                throughput.component_mul_assign(&material.color);
+            }
+        } else {
+            throughput.component_mul_assign(&material.color);
+
+            let n_dot_l = normal.dot(&scene.light.sun_direction).max(0.0);
+
+            if n_dot_l > 0.0 && raycast(scene.octree, surface_pos, scene.light.sun_direction).is_none() {
+                radiance += throughput.component_mul(&scene.light.sun_color) * n_dot_l;
+            }
+
+            if bounce == scene.settings.max_bounces {
+                break;
+            }
+
+            is_specular = false;
+            origin = surface_pos;
+            direction = sample_hemisphere(normal, [rng.next_float(), rng.next_float()]);
+        }
+
+        hit = raycast(scene.octree, origin, direction);
+    }
+
+    radiance
+}
+
+/// Colour of a single sample, the render pass averages these into the HDR target
+fn shade(scene: &Scene, origin: Vec3, direction: Vec3, res: &TraceResult, pixel: [u32; 2], rng: &mut Rng) -> Vec3 {
+    let view_mode = ViewMode::ALL[scene.settings.view_mode as usize];
+
+    // lit views fade hits into the sky behind them
+    let fog = | color: Vec3 | match res.hit {
+        Some(hit) => apply_fog(&scene.light, color, direction, hit.dist),
+        None => color
+    };
+
+    match (view_mode, res.hit) {
+        (ViewMode::Shaded, hit) => fog(shade_through_specular(scene, origin, direction, hit, pixel)),
+        (ViewMode::Depth, Some(hit)) => Vec3::repeat(1.0 / (1.0 + hit.dist)),
+        (ViewMode::Normal, Some(hit)) => hit.normal() * 0.5 + Vec3::repeat(0.5),
+        (ViewMode::OctreeDepth, _) => heatmap(res.depth_reached as f32 / MAX_WALK_DEPTH as f32),
+        (ViewMode::Steps, _) => heatmap(res.box_tests as f32 / HEATMAP_MAX_STEPS),
+        (ViewMode::NodeHash, Some(hit)) => hash_color(hit.node_index),
+        (ViewMode::HitMask, hit) => Vec3::repeat(hit.is_some() as u32 as f32),
+        (ViewMode::PathTraced, hit) => fog(trace_path(scene, origin, direction, hit, rng)),
+
+        (_, None) => Vec3::zeros()
+    }
+}
+
+fn halton(mut idx: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+
+    while idx > 0 {
+        fraction /= base as f32;
+        result += fraction * (idx % base) as f32;
+        idx /= base;
+    }
+
+    result
+}
+
+fn sample_offsets(antialiasing: Antialiasing) -> &'static [[f32; 2]] {
+    match antialiasing {
+        Antialiasing::None => &[[0.0, 0.0]],
+        Antialiasing::Grid2x2 => &GRID_2X2_OFFSETS,
+        Antialiasing::Rgss => &RGSS_OFFSETS
+    }
+}
+
+// same as in the shader, moves the whole sample pattern with every sample staying in its own row and column
+fn frame_jitter(antialiasing: Antialiasing, frame_index: u32) -> [f32; 2] {
+    let spacing = match antialiasing {
+        Antialiasing::None => 1.0,
+        Antialiasing::Grid2x2 => 0.5,
+        Antialiasing::Rgss => 0.25
+    };
+
+    [halton(frame_index + 1, 2) - 0.5, halton(frame_index + 1, 3) - 0.5].map(| j | j * spacing)
+}
+
+/// Colour the render pass writes into the HDR target, averaged over every accumulated frame
+fn render_pixel(scene: &Scene, camera: &CameraData, size: Extent, pixel: [u32; 2]) -> Vec3 {
+    let settings = &scene.settings;
+    let antialiasing = Antialiasing::ALL[settings.antialiasing as usize];
+    let sample_count = antialiasing.sample_count() as f32;
+
+    let accumulates = settings.view_mode == ViewMode::PathTraced as u32
+        || (settings.view_mode == ViewMode::Shaded as u32 && settings.jitter != 0);
+    let first_frame = if accumulates { 0 } else { settings.frame_index };
+    // pixels are 1 / size apart at unit distance, the shorter side has the larger ones
+    let lod_angle = settings.lod_pixels / size.width.min(size.height) as f32;
+
+    let frame_color = | frame: u32 | {
+        let jitter = if settings.jitter != 0 { frame_jitter(antialiasing, frame) } else { [0.0; 2] };
+        let mut rng = Rng::new(pixel, frame);
+
+        sample_offsets(antialiasing).iter()
+            .map(| offset | {
+                let ray_cord = Vec3::new(
+                    (pixel[0] as f32 + 0.5 + offset[0] + jitter[0]) / size.width as f32 - 0.5,
+                    (pixel[1] as f32 + 0.5 + offset[1] + jitter[1]) / size.height as f32 - 0.5,
+                    1.0
+                );
+                let direction = camera.basis * ray_cord.normalize();
+                let res = trace(scene.octree, camera.pos, direction, lod_angle);
+
+                shade(scene, camera.pos, direction, &res, pixel, &mut rng)
+            })
+            .sum::<Vec3>() / sample_count
+    };
+
+    (first_frame..=settings.frame_index).map(frame_color).sum::<Vec3>() / (settings.frame_index - first_frame + 1) as f32
+}
+
+/// What the post pass makes of a colour from `render_pixel`
+fn post_process(scene: &Scene, mut color: Vec3) -> Vec3 {
+    let is_lit = [ViewMode::Shaded as u32, ViewMode::PathTraced as u32].contains(&scene.settings.view_mode);
+
+    if is_lit {
+        color = tonemap(color * scene.post.exposure, Tonemapper::ALL[scene.post.tonemapper as usize]);
+
+        if scene.post.encode_srgb != 0 {
+            color = linear_to_srgb(color);
+        }
+    }
+
+    color
+}
+
+/// Renders an RGB image the same way rendering_shader.comp does
+pub fn render(scene: &Scene, camera: &CameraData, size: Extent) -> Vec<[u8; 3]> {
+    (0..size.height)
+        .flat_map(| y | (0..size.width).map(move | x | (x, y)))
+        .map(| (x, y) | {
+            let color = post_process(scene, render_pixel(scene, camera, size, [x, y]));
+
+            color.map(| c | (c.clamp(0.0, 1.0) * 255.0).round() as u8).into()
+        })
+        .collect()
+}
+
+/// Binary PPM, viewable by most image viewers without any extra dependencies
+pub fn write_ppm(path: &Path, size: Extent, pixels: &[[u8; 3]]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    write!(file, "P6\n{} {}\n255\n", size.width, size.height)?;
+    file.write_all(pixels.as_flattened())?;
+
+    file.flush()
+}
+
+
+
+#[test]
+fn test_reference_trace() {
+    use super::{camera::CamBasis, voxel_data_generator::{generate_tree, PALETTE}};
+
+    let octree = generate_tree(4);
+    let camera = CamBasis { pos: Vec3::new(0.2, 0.3, -3.0), ..Default::default() }.build_camera_data();
+
+    let size = Extent { width: 32, height: 32 };
+    let results: Vec<_> = (0..size.width * size.height)
+        .map(| idx | {
+            let ray_cord = Vec3::new((idx % size.width) as f32 / 32.0 - 0.5, (idx / size.width) as f32 / 32.0 - 0.5, 1.0);
+
+            trace(&octree, camera.pos, camera.basis * ray_cord.normalize(), 0.0)
+        })
+        .collect();
+
+    assert!(results.iter().any(| res | res.hit.is_some()));
+    assert!(results.iter().any(| res | res.hit.is_none()));
+
+    for res in &results {
+        let Some(hit) = res.hit else { continue };
+
+        assert_eq!(res.depth_reached, hit.depth);
+        assert!(hit.dist > 0.0 && hit.node_index != 0);
+        // axis aligned unit normal
+        assert_eq!(hit.normal().abs().sum(), 1.0);
+    }
+
+    // no ambient occlusion, so every hit gets some ambient light and misses show the sky
+    let scene = | view_mode: ViewMode | Scene {
+        octree: &octree,
+        palette: &PALETTE,
+        light: LightData { sun_direction: -Vec3::z(), sun_color: Vec3::repeat(1.0), ambient_color: Vec3::repeat(0.1), fog_density: 0.0 },
+        settings: RenderSettings { view_mode: view_mode as u32, ao_samples: 0, ao_radius: 0.0, frame_index: 0, max_bounces: 0, antialiasing: 0, jitter: 0, lod_pixels: 0.0 },
+        post: PostSettings { exposure: 1.0, tonemapper: Tonemapper::Aces as u32, encode_srgb: 1 }
+    };
+    let mask = render(&scene(ViewMode::HitMask), &camera, size);
+    let depth = render(&scene(ViewMode::Depth), &camera, size);
+    let shaded = render(&scene(ViewMode::Shaded), &camera, size);
+
+    assert!(shaded.iter().all(| pixel | pixel != &[0; 3]));
+
+    // debug views stay black where nothing was hit
+    for (mask, depth) in mask.iter().zip(&depth) {
+        assert_eq!(mask[0] == 0, depth == &[0; 3]);
+    }
+}
+
+// octree on a grid of 2^depth voxels, each voxel with its palette entry
+#[cfg(test)]
+fn test_octree(depth: u32, voxels: &[(super::camera::UVec3, u32)]) -> Vec<VoxelData> {
+    use super::builder;
+
+    let mut voxels: Vec<_> = voxels.iter().map(| &(voxel, palette_idx) | (builder::morton_code(voxel, depth), palette_idx)).collect();
+    voxels.sort();
+
+    builder::build_sorted(depth, voxels)
+}
+
+// a night sky with neither sun nor ambient light, tests turn on what they need
+#[cfg(test)]
+fn test_scene<'a>(octree: &'a [VoxelData], palette: &'a [PaletteEntry]) -> Scene<'a> {
+    Scene {
+        octree,
+        palette,
+        light: LightData { sun_direction: Vec3::y(), sun_color: Vec3::zeros(), ambient_color: Vec3::zeros(), fog_density: 0.0 },
+        settings: RenderSettings::default(),
+        post: PostSettings { exposure: 1.0, tonemapper: Tonemapper::None as u32, encode_srgb: 0 }
+    }
+}
+
+#[test]
+fn test_ambient_occlusion() {
+    use super::camera::UVec3;
+
+    // a floor of 4x4 voxels with its top at y = 0.5, and a wall on its -X edge with its face at x = -0.5
+    let mut voxels: Vec<_> = (0..16).map(| idx | (UVec3::new(idx % 4, 3, idx / 4), 0)).collect();
+    voxels.extend((0..4).map(| z | (UVec3::new(0, 2, z), 0)));
+
+    let octree = test_octree(2, &voxels);
+    let palette = [PaletteEntry::diffuse(Vec3::repeat(1.0))];
+    let scene = Scene {
+        settings: RenderSettings { ao_samples: 16, ao_radius: 0.5, ..Default::default() },
+
+        ..test_scene(&octree, &palette)
+    };
+    let ao = | x: f32 | ambient_occlusion(&scene, Vec3::new(x, 0.5 - SECONDARY_RAY_BIAS, 0.1), -Vec3::y(), [3, 7]);
+
+    // further from the wall than the radius, nothing occludes
+    assert_eq!(ao(0.75), 1.0);
+    assert!(ao(-0.45) < 0.75);
+}
+
+#[test]
+fn test_path_tracing() {
+    use super::camera::UVec3;
+
+    // a floor voxel with its top at y = 0.5, and an emitter beside the space above it
+    let floor = (UVec3::new(1, 3, 1), 0);
+    let emitter = (UVec3::new(2, 2, 1), 1);
+    let palette = [PaletteEntry::diffuse(Vec3::new(0.8, 0.5, 0.2)), PaletteEntry::emissive(Vec3::repeat(1.0), 10.0)];
+    let (origin, direction) = (Vec3::new(-0.25, -3.0, -0.25), Vec3::y());
+
+    let octree = test_octree(2, &[floor, emitter]);
+    let dark_octree = test_octree(2, &[floor]);
+    let scene = | octree, light, max_bounces | Scene {
+        light,
+        settings: RenderSettings { max_bounces, ..Default::default() },
+
+        ..test_scene(octree, &palette)
+    };
+    // the floor as the path tracer sees it, averaged over many paths
+    let floor_color = | scene: &Scene | {
+        let hit = raycast(scene.octree, origin, direction);
+
+        (0..256).map(| idx | trace_path(scene, origin, direction, hit, &mut Rng::new([idx, 0], 0))).sum::<Vec3>() / 256.0
+    };
+
+    // without bounces only the sun lights the floor, as in the shaded view without ambient light
+    let sun = LightData { sun_direction: Vec3::new(-0.3, -1.0, 0.2).normalize(), sun_color: Vec3::new(1.0, 0.9, 0.8), ..test_scene(&octree, &palette).light };
+    let direct = scene(&octree, sun, 0);
+    let hit = raycast(&octree, origin, direction).unwrap();
+
+    assert!((floor_color(&direct) - shade_hit(&direct, origin, direction, &hit, [0, 0])).norm() < 1e-5);
+    assert!(floor_color(&direct).min() > 0.0);
+
+    // at night the emitter is the only light, and only paths bouncing off the floor find it
+    let night = test_scene(&octree, &palette).light;
+
+    assert_eq!(floor_color(&scene(&octree, night, 0)), Vec3::zeros());
+    assert!(floor_color(&scene(&octree, night, 2)).min() > 0.1);
+    // only the night sky is left without the emitter
+    assert!(floor_color(&scene(&dark_octree, night, 2)).max() < 0.01);
+}
+
+#[test]
+fn test_materials() {
+    use super::camera::UVec3;
+
+    // leaving glass upwards, so the normal against the ray points down. The critical angle of 1.5 is 41.8 degrees
+    let leaving = | degrees: f32 | refract(Vec3::new(degrees.to_radians().sin(), -degrees.to_radians().cos(), 0.0), Vec3::y(), 1.5);
+
+    assert_eq!(leaving(45.0), Vec3::zeros());
+    assert!((leaving(30.0).x - 0.75).abs() < 1e-6);
+    assert!((fresnel(1.0, 1.5) - 0.04).abs() < 1e-6);
+    assert_eq!(fresnel(0.0, 1.5), 1.0);
+
+    // a single voxel filling the -X -Y -Z octant of the root
+    let octree = test_octree(1, &[(UVec3::zeros(), 0)]);
+    let hit_on = | origin: Vec3, direction: Vec3 | {
+        let hit = raycast(&octree, origin, direction).unwrap();
+
+        (origin + direction * hit.dist, hit)
+    };
+
+    // through the -Z face and out of the +Z one, shifted sideways but parallel to the way in
+    let direction = Vec3::new(0.2, 0.1, 1.0).normalize();
+    let (hit_pos, hit) = hit_on(Vec3::new(-0.7, -0.5, -3.0), direction);
+    let mut out_dir = direction;
+    let exit_pos = pass_through_glass(hit_pos, &mut out_dir, &hit, 1.5);
+
+    assert_eq!(hit.face, Face::NegZ);
+    assert!((out_dir - direction).norm() < 1e-5);
+    assert!((exit_pos.z - SECONDARY_RAY_BIAS).abs() < 1e-5);
+    assert!((exit_pos - hit_pos).normalize().dot(&direction) < 0.9999);
+
+    // lit by nothing else, an emitter shows its own light
+    let emissive = [PaletteEntry::emissive(Vec3::new(1.0, 0.5, 0.25), 4.0)];
+    let (origin, direction) = (Vec3::new(-0.5, -3.0, -0.5), Vec3::y());
+    let (_, hit) = hit_on(origin, direction);
+
+    assert_eq!(shade_hit(&test_scene(&octree, &emissive), origin, direction, &hit, [0, 0]), Vec3::new(4.0, 2.0, 1.0));
+
+    // a smooth metal is a tinted mirror of the sky, in the shaded view and the path traced one
+    let metal = [PaletteEntry::metal(Vec3::new(0.9, 0.6, 0.3), 0.0)];
+    let scene = Scene {
+        light: LightData { sun_direction: Vec3::new(0.5, -1.0, 0.3).normalize(), sun_color: Vec3::repeat(2.0), ..test_scene(&octree, &metal).light },
+        settings: RenderSettings { max_bounces: 1, ..Default::default() },
+
+        ..test_scene(&octree, &metal)
+    };
+    let (origin, direction) = (Vec3::new(-0.8, -3.0, -0.7), Vec3::new(0.3, 1.0, 0.2).normalize());
+    let (_, hit) = hit_on(origin, direction);
+    let mirrored = metal[0].color.component_mul(&sky_color(&scene.light, Vec3::new(direction.x, -direction.y, direction.z), true));
+
+    assert_eq!(hit.face, Face::NegY);
+    assert!((shade_through_specular(&scene, origin, direction, Some(hit), [0, 0]) - mirrored).norm() < 1e-5);
+    assert!((trace_path(&scene, origin, direction, Some(hit), &mut Rng::new([0, 0], 0)) - mirrored).norm() < 1e-5);
+}
+
+#[test]
+fn test_fog() {
+    let light = LightData { sun_direction: Vec3::new(0.2, -0.6, 0.3).normalize(), sun_color: Vec3::repeat(3.0), ambient_color: Vec3::zeros(), fog_density: 0.5 };
+    let (color, direction) = (Vec3::new(0.9, 0.1, 0.4), Vec3::new(0.6, -0.2, 0.7).normalize());
+    let sky = sky_color(&light, direction, false);
+    let distance_to_sky = | dist: f32 | (apply_fog(&light, color, direction, dist) - sky).norm();
+
+    assert!((apply_fog(&light, color, direction, 0.0) - color).norm() < 1e-6);
+    assert!(distance_to_sky(1.0) < distance_to_sky(0.0) && distance_to_sky(4.0) < distance_to_sky(1.0));
+    assert!(distance_to_sky(100.0) < 1e-6);
+}
+
+#[test]
+fn test_tonemapping() {
+    let inputs: Vec<f32> = (0..=400).map(| idx | idx as f32 * 0.05).chain([100.0, 1000.0]).collect();
+
+    for tonemapper in Tonemapper::ALL {
+        let mapped: Vec<f32> = inputs.iter().map(| &c | tonemap(Vec3::repeat(c), tonemapper).x).collect();
+
+        assert_eq!(mapped[0], 0.0);
+        assert!(mapped.windows(2).all(| pair | pair[0] <= pair[1]), "{tonemapper:?} is not monotonic");
+    }
+
+    assert!(inputs.iter().all(| &c | tonemap(Vec3::repeat(c), Tonemapper::Reinhard).x < 1.0));
+    // the ACES fit reaches one at about 7.2 and is clamped there
+    assert!(inputs.iter().all(| &c | tonemap(Vec3::repeat(c), Tonemapper::Aces).x <= 1.0));
+    assert!(tonemap(Vec3::repeat(7.0), Tonemapper::Aces).x < 1.0);
+}
+
+#[test]
+fn test_sample_positions() {
+    assert_eq!(sample_offsets(Antialiasing::None), [[0.0; 2]]);
+
+    // 2x2 is the centres of the pixel's quarters
+    let grid = sample_offsets(Antialiasing::Grid2x2);
+
+    assert_eq!(grid.len(), 4);
+    assert!([-0.25, 0.25].iter().all(| &x | [-0.25, 0.25].iter().all(| &y | grid.contains(&[x, y]))));
+
+    // the rotated grid is unchanged by a quarter turn, and has every sample on its own row and
+    // column of a 4x4 grid
+    let rgss = sample_offsets(Antialiasing::Rgss);
+
+    assert!(rgss.iter().all(| &[x, y] | rgss.contains(&[-y, x])));
+    for axis in 0..2 {
+        let mut centres: Vec<f32> = rgss.iter().map(| offset | offset[axis]).collect();
+        centres.sort_by(f32::total_cmp);
+
+        assert_eq!(centres, [-0.375, -0.125, 0.125, 0.375]);
+    }
+
+    // jittered samples move every frame, but never out of the pixel
+    for antialiasing in Antialiasing::ALL {
+        let jitters: Vec<[f32; 2]> = (0..64).map(| frame | frame_jitter(antialiasing, frame)).collect();
+
+        assert!(jitters.windows(2).all(| pair | pair[0] != pair[1]));
+
+        for offset in sample_offsets(antialiasing) {
+            for jitter in &jitters {
+                assert!((0..2).all(| axis | (offset[axis] + jitter[axis]).abs() < 0.5), "{antialiasing:?} sample leaves the pixel");
+            }
+        }
+    }
+}
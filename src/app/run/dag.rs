@@ -0,0 +1,168 @@
+//! Sparse voxel DAG: identical subtrees of the octree are stored once.
+//! The shader only follows child indices, so a DAG renders exactly like the tree it came from
+
+use std::{collections::HashMap, fmt};
+
+use super::{gpu_layout::{GpuType, LayoutRules}, gpu_shared_data::{VoxelData, EMPTY_NODE}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionReport {
+    pub nodes_before: usize,
+    pub nodes_after: usize
+}
+
+impl CompressionReport {
+    pub fn ratio(&self) -> f32 {
+        self.nodes_before as f32 / self.nodes_after.max(1) as f32
+    }
+}
+
+impl fmt::Display for CompressionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kib = | nodes: usize | (nodes * LayoutRules::Std430.array_stride(VoxelData::STD430)) as f32 / 1024.0;
+
+        write!(
+            f, "{} -> {} nodes, {:.1} -> {:.1} KiB, {:.1}x smaller",
+            self.nodes_before, self.nodes_after, kib(self.nodes_before), kib(self.nodes_after), self.ratio()
+        )
+    }
+}
+
+struct Deduplicator<'a> {
+    octree: &'a [VoxelData],
+    dag: Vec<VoxelData>,
+    unique: HashMap<VoxelData, u32>,
+    // input may share nodes already, those are only visited once
+    remapped: HashMap<u32, u32>
+}
+
+impl Deduplicator<'_> {
+    // children first, so equal subtrees end up as equal nodes
+    fn dedup(&mut self, index: u32) -> u32 {
+        if let Some(&new_index) = self.remapped.get(&index) {
+            return new_index;
+        }
+
+        let mut node = self.octree[index as usize];
+
+        for child in node.child_indicies.iter_mut().filter(| child | **child != EMPTY_NODE) {
+            *child = self.dedup(*child);
+        }
+
+        let new_index = *self.unique.entry(node)
+            .or_insert_with(|| {
+                self.dag.push(node);
+
+                (self.dag.len() - 1) as u32
+            });
+
+        self.remapped.insert(index, new_index);
+
+        new_index
+    }
+}
+
+/// Merges identical subtrees and drops unreachable nodes. Root stays at index 0
+pub fn compress(octree: &[VoxelData]) -> (Vec<VoxelData>, CompressionReport) {
+    let mut deduplicator = Deduplicator {
+        octree,
+        // root is never a child, its slot is filled in last
+        dag: vec![octree[0]],
+        unique: HashMap::new(),
+        remapped: HashMap::new()
+    };
+
+    let mut root = octree[0];
+
+    for child in root.child_indicies.iter_mut().filter(| child | **child != EMPTY_NODE) {
+        *child = deduplicator.dedup(*child);
+    }
+
+    let mut dag = deduplicator.dag;
+    dag[0] = root;
+
+    let report = CompressionReport { nodes_before: octree.len(), nodes_after: dag.len() };
+
+    (dag, report)
+}
+
+/// Makes the node at the end of `path` (child slots from the root) safe to edit, copying
+/// every node on the way which is shared with another part of the tree. Returns its index.
+/// Replaced nodes may be left unreachable, `compress` drops them
+pub fn unshare(dag: &mut Vec<VoxelData>, path: &[usize]) -> u32 {
+    let mut references = vec![0u32; dag.len()];
+
+    for &child in dag.iter().flat_map(| node | &node.child_indicies).filter(| child | **child != EMPTY_NODE) {
+        references[child as usize] += 1;
+    }
+
+    let mut parent = 0;
+    // children of a copy gain a parent, so everything below the first copy is copied too
+    let mut copying = false;
+
+    for &slot in path {
+        let mut child = dag[parent as usize].child_indicies[slot];
+        assert!(child != EMPTY_NODE, "node {parent} has no child in slot {slot}");
+
+        copying |= references[child as usize] > 1;
+
+        if copying {
+            dag.push(dag[child as usize]);
+            child = (dag.len() - 1) as u32;
+
+            dag[parent as usize].child_indicies[slot] = child;
+        }
+
+        parent = child;
+    }
+
+    parent
+}
+
+
+
+#[test]
+fn test_dag() {
+    use super::{camera::Vec3, raycast::raycast, voxel_data_generator::generate_tree};
+
+    let octree = generate_tree(4);
+    let (mut dag, report) = compress(&octree);
+
+    assert_eq!(report.nodes_before, octree.len());
+    assert!(report.nodes_after < report.nodes_before && report.ratio() > 1.0);
+    // already minimal
+    assert_eq!(compress(&dag).0, dag);
+
+    // rays through the centre of every column of the leaf grid
+    let rays: Vec<_> = (0..64)
+        .map(| idx | Vec3::new(-0.875 + (idx % 8) as f32 * 0.25, -0.875 + (idx / 8) as f32 * 0.25, -3.0))
+        .collect();
+    let palette_hits = | octree: &[VoxelData] | rays.iter()
+        .map(| &origin | raycast(octree, origin, Vec3::z()).map(| hit | (hit.coords(), octree[hit.node_index as usize].pallete_idx)))
+        .collect::<Vec<_>>();
+
+    let tree_hits = palette_hits(&octree);
+    assert_eq!(palette_hits(&dag), tree_hits);
+
+    // repaint a single voxel, every other one keeps its palette entry
+    let target = rays.iter().find_map(| &origin | raycast(&dag, origin, Vec3::z())).unwrap();
+    let leaf = unshare(&mut dag, target.path());
+    dag[leaf as usize].pallete_idx += 100;
+
+    for (before, after) in tree_hits.iter().zip(palette_hits(&dag)) {
+        match (before, after) {
+            (Some((coords, _)), Some((_, palette))) if *coords == target.coords() => assert!(palette >= 100),
+            _ => assert_eq!(*before, after)
+        }
+    }
+
+    // second edit of the same voxel does not copy anything
+    let len = dag.len();
+    assert_eq!(unshare(&mut dag, target.path()), leaf);
+    assert_eq!(dag.len(), len);
+
+    // undoing the edit and compressing again drops the copies and the nodes they replaced
+    dag[leaf as usize].pallete_idx -= 100;
+    assert!(dag.len() > report.nodes_after);
+    assert_eq!(compress(&dag).0.len(), report.nodes_after);
+}
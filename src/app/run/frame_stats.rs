@@ -0,0 +1,49 @@
+use std::fmt;
+
+/// Frame time samples collected during `--benchmark`
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+    frame_times: Vec<f32>
+}
+
+impl FrameStats {
+    pub fn push(&mut self, delta: f32) {
+        self.frame_times.push(delta);
+    }
+
+    pub fn total_time(&self) -> f32 {
+        self.frame_times.iter().sum()
+    }
+
+    /// Frame time in seconds below which `fraction` of all frames are
+    fn percentile(sorted: &[f32], fraction: f32) -> f32 {
+        let idx = ((sorted.len() - 1) as f32 * fraction).round() as usize;
+
+        sorted[idx]
+    }
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.frame_times.is_empty() {
+            return f.write_str("no frames rendered");
+        }
+
+        let mut sorted = self.frame_times.clone();
+        sorted.sort_by(f32::total_cmp);
+
+        let average = self.total_time() / sorted.len() as f32;
+
+        write!(
+            f,
+            "{} frames, avg {:.3} ms ({:.1} fps), median {:.3} ms, p95 {:.3} ms, min {:.3} ms, max {:.3} ms",
+            sorted.len(),
+            average * 1000.0,
+            1.0 / average,
+            Self::percentile(&sorted, 0.5) * 1000.0,
+            Self::percentile(&sorted, 0.95) * 1000.0,
+            sorted[0] * 1000.0,
+            sorted[sorted.len() - 1] * 1000.0
+        )
+    }
+}
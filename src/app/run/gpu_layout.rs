@@ -0,0 +1,256 @@
+//! std140/std430 layouts for data shared with shaders.
+//! Rust layout of a struct has nothing to do with GLSL block layout, so instead of
+//! `#[repr(align(16))]` tricks every shared type knows its GLSL size and alignment
+//! and serializes itself into raw bytes at the right offsets.
+
+use core::mem::MaybeUninit;
+
+use super::camera::{Mat3, Mat4};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutRules {
+    /// Uniform blocks
+    Std140,
+    /// Storage blocks
+    Std430
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeLayout {
+    pub size: usize,
+    pub align: usize
+}
+
+pub const fn align_up(value: usize, align: usize) -> usize {
+    value.div_ceil(align) * align
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+impl LayoutRules {
+    pub const fn layout_of<T: GpuType>(self) -> TypeLayout {
+        match self {
+            Self::Std140 => T::STD140,
+            Self::Std430 => T::STD430
+        }
+    }
+
+    /// Alignment of arrays and structs containing an element with `align`
+    const fn aggregate_align(self, align: usize) -> usize {
+        match self {
+            Self::Std140 => align_up(align, 16),
+            Self::Std430 => align
+        }
+    }
+
+    /// Distance between elements of `T[]`
+    pub const fn array_stride(self, element: TypeLayout) -> usize {
+        align_up(element.size, self.aggregate_align(element.align))
+    }
+
+    /// Offsets of struct members in declaration order and layout of the whole struct
+    pub const fn struct_layout<const N: usize>(self, members: [TypeLayout; N]) -> ([usize; N], TypeLayout) {
+        let mut offsets = [0; N];
+        let mut offset = 0;
+        let mut align = 1;
+
+        let mut idx = 0;
+        while idx < N {
+            offset = align_up(offset, members[idx].align);
+            offsets[idx] = offset;
+
+            offset += members[idx].size;
+            align = max(align, members[idx].align);
+
+            idx += 1;
+        }
+
+        let align = self.aggregate_align(align);
+
+        (offsets, TypeLayout { size: align_up(offset, align), align })
+    }
+}
+
+
+pub trait GpuType: Sized {
+    const STD140: TypeLayout;
+    const STD430: TypeLayout;
+
+    /// Writes value to the beginning of `dst`. Padding bytes are left untouched
+    fn write(&self, rules: LayoutRules, dst: &mut [u8]);
+
+    fn to_bytes(&self, rules: LayoutRules) -> Vec<u8> {
+        let mut bytes = vec![0; rules.layout_of::<Self>().size];
+
+        self.write(rules, &mut bytes);
+
+        bytes
+    }
+}
+
+/// Writes single value into mapped memory, padding is zeroed
+pub fn write_mapped<T: GpuType>(rules: LayoutRules, value: &T, dst: &mut [MaybeUninit<u8>]) {
+    copy_to_uninit(&value.to_bytes(rules), dst);
+}
+
+/// Writes `values` as GLSL array (or runtime sized block member) into mapped memory, padding is zeroed
+pub fn write_mapped_array<T: GpuType>(rules: LayoutRules, values: &[T], dst: &mut [MaybeUninit<u8>]) {
+    let stride = rules.array_stride(rules.layout_of::<T>());
+    let mut bytes = vec![0; stride * values.len()];
+
+    for (value, dst) in values.iter().zip(bytes.chunks_exact_mut(stride)) {
+        value.write(rules, dst);
+    }
+
+    copy_to_uninit(&bytes, dst);
+}
+
+fn copy_to_uninit(src: &[u8], dst: &mut [MaybeUninit<u8>]) {
+    assert!(dst.len() >= src.len(), "mapped memory is too small: {} < {}", dst.len(), src.len());
+
+    dst.iter_mut()
+        .zip(src)
+        .for_each(| (dst, src) | { dst.write(*src); });
+}
+
+
+macro_rules! impl_scalar {
+    ($($ty:ty),*) => {$(
+        impl GpuType for $ty {
+            const STD140: TypeLayout = TypeLayout { size: 4, align: 4 };
+            const STD430: TypeLayout = TypeLayout { size: 4, align: 4 };
+
+            fn write(&self, _rules: LayoutRules, dst: &mut [u8]) {
+                dst[..4].copy_from_slice(&self.to_le_bytes());
+            }
+        }
+    )*};
+}
+
+impl_scalar!(u32, i32, f32);
+
+// vec3 is aligned as vec4, but takes only 12 bytes, so a scalar can be packed after it
+macro_rules! impl_vector {
+    ($($len:literal => $align:literal),*) => {$(
+        impl<T: GpuType + nalgebra::Scalar> GpuType for nalgebra::SVector<T, $len> {
+            const STD140: TypeLayout = TypeLayout { size: T::STD140.size * $len, align: T::STD140.align * $align };
+            const STD430: TypeLayout = TypeLayout { size: T::STD430.size * $len, align: T::STD430.align * $align };
+
+            fn write(&self, rules: LayoutRules, dst: &mut [u8]) {
+                let size = rules.layout_of::<T>().size;
+
+                for (component, dst) in self.iter().zip(dst.chunks_mut(size)) {
+                    component.write(rules, dst);
+                }
+            }
+        }
+    )*};
+}
+
+impl_vector!(2 => 2, 3 => 4, 4 => 4);
+
+// matrices are arrays of column vectors
+macro_rules! impl_matrix {
+    ($($ty:ty => $columns:literal),*) => {$(
+        impl GpuType for $ty {
+            const STD140: TypeLayout = TypeLayout { size: 16 * $columns, align: 16 };
+            const STD430: TypeLayout = TypeLayout { size: 16 * $columns, align: 16 };
+
+            fn write(&self, rules: LayoutRules, dst: &mut [u8]) {
+                for (column, dst) in self.column_iter().zip(dst.chunks_mut(16)) {
+                    column.into_owned().write(rules, dst);
+                }
+            }
+        }
+    )*};
+}
+
+impl_matrix!(Mat3 => 3, Mat4 => 4);
+
+impl<T: GpuType, const N: usize> GpuType for [T; N] {
+    const STD140: TypeLayout = TypeLayout {
+        size: LayoutRules::Std140.array_stride(T::STD140) * N,
+        align: LayoutRules::Std140.aggregate_align(T::STD140.align)
+    };
+    const STD430: TypeLayout = TypeLayout {
+        size: LayoutRules::Std430.array_stride(T::STD430) * N,
+        align: LayoutRules::Std430.aggregate_align(T::STD430.align)
+    };
+
+    fn write(&self, rules: LayoutRules, dst: &mut [u8]) {
+        let stride = rules.array_stride(rules.layout_of::<T>());
+
+        for (element, dst) in self.iter().zip(dst.chunks_mut(stride)) {
+            element.write(rules, dst);
+        }
+    }
+}
+
+
+/// Declares a struct shared with shaders and implements [`GpuType`] for it.
+/// Field order must follow the GLSL declaration
+macro_rules! gpu_struct {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $( $(#[$field_attr:meta])* $field_vis:vis $field:ident : $ty:ty ),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $( $(#[$field_attr])* $field_vis $field: $ty ),*
+        }
+
+        impl $name {
+            /// Byte offsets of fields in declaration order
+            pub const fn field_offsets(rules: $crate::app::run::gpu_layout::LayoutRules) -> [usize; [$(stringify!($field)),*].len()] {
+                rules.struct_layout([$( rules.layout_of::<$ty>() ),*]).0
+            }
+        }
+
+        impl $crate::app::run::gpu_layout::GpuType for $name {
+            const STD140: $crate::app::run::gpu_layout::TypeLayout =
+                $crate::app::run::gpu_layout::LayoutRules::Std140.struct_layout([$( <$ty as $crate::app::run::gpu_layout::GpuType>::STD140 ),*]).1;
+            const STD430: $crate::app::run::gpu_layout::TypeLayout =
+                $crate::app::run::gpu_layout::LayoutRules::Std430.struct_layout([$( <$ty as $crate::app::run::gpu_layout::GpuType>::STD430 ),*]).1;
+
+            fn write(&self, rules: $crate::app::run::gpu_layout::LayoutRules, dst: &mut [u8]) {
+                let mut offsets = Self::field_offsets(rules).into_iter();
+
+                $( $crate::app::run::gpu_layout::GpuType::write(&self.$field, rules, &mut dst[offsets.next().unwrap()..]); )*
+            }
+        }
+    };
+}
+
+pub(crate) use gpu_struct;
+
+
+
+#[test]
+fn test_layouts() {
+    use super::camera::Vec3;
+
+    assert_eq!(LayoutRules::Std140.layout_of::<Vec3>(), TypeLayout { size: 12, align: 16 });
+    assert_eq!(LayoutRules::Std140.layout_of::<[f32; 3]>(), TypeLayout { size: 48, align: 16 });
+    assert_eq!(LayoutRules::Std430.layout_of::<[f32; 3]>(), TypeLayout { size: 12, align: 4 });
+
+    // float directly after vec3 fits into its padding
+    assert_eq!(LayoutRules::Std140.struct_layout([Vec3::STD140, f32::STD140]), ([0, 12], TypeLayout { size: 16, align: 16 }));
+    // but not after an array
+    assert_eq!(LayoutRules::Std140.struct_layout([<[f32; 3]>::STD140, f32::STD140]), ([0, 48], TypeLayout { size: 64, align: 16 }));
+
+    let mat = Mat3::new(
+        1.0, 2.0, 3.0,
+        4.0, 5.0, 6.0,
+        7.0, 8.0, 9.0
+    );
+    let bytes = mat.to_bytes(LayoutRules::Std140);
+
+    assert_eq!(bytes.len(), 48);
+    // second column, first row
+    assert_eq!(bytes[16..20], 2.0f32.to_le_bytes());
+    assert_eq!(bytes[12..16], [0; 4]);
+}
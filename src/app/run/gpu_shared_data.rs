@@ -1,37 +1,267 @@
-//use qubicon_vulkan::memory::resources::mapped_resource::MappableType;
+use crate::app::spirv::reflect::{DescriptorKind, ExpectedBinding, ExpectedBlock};
 
-use super::camera::{Mat3, Mat4, Vec3};
+use super::{camera::{Mat3, Vec3}, gpu_layout::{gpu_struct, GpuType, LayoutRules}};
 
-#[repr(align(16))]
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct AlignedVec(Vec3);
-#[repr(align(16))]
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct AlignedMat(Mat3);
+pub const RENDER_DATA_BINDING: u32 = 0;
+pub const VOXEL_DATA_BINDING: u32 = 1;
+pub const RENDER_TARGET_BINDING: u32 = 2;
+pub const PALETTE_BINDING: u32 = 3;
+pub const ACCUMULATION_BINDING: u32 = 4;
+pub const HDR_TARGET_BINDING: u32 = 5;
+pub const COMPACT_NODES_BINDING: u32 = 6;
 
-impl From<Vec3> for AlignedVec {
-    fn from(value: Vec3) -> Self {
-        Self (value)
+// workgroup size of the rendering shader
+pub const TILE_WIDTH_SPEC_ID: u32 = 0;
+pub const TILE_HEIGHT_SPEC_ID: u32 = 1;
+// which half of the frame a pipeline runs, the shader is built once for each
+pub const PASS_SPEC_ID: u32 = 2;
+/// Traces rays into the HDR target
+pub const PASS_RENDER: u32 = 0;
+/// Tonemaps the HDR target into the render target
+pub const PASS_POST: u32 = 1;
+// `NodeLayout` discriminant, selects which of the two octree buffers the shader walks
+pub const NODE_LAYOUT_SPEC_ID: u32 = 3;
+
+// `PaletteEntry::material` kinds
+pub const MATERIAL_DIFFUSE: u32 = 0;
+pub const MATERIAL_EMISSIVE: u32 = 1;
+pub const MATERIAL_METAL: u32 = 2;
+pub const MATERIAL_GLASS: u32 = 3;
+
+/// Child index of an empty slot. Every index into the octree buffer is a valid child, the root included
+pub const EMPTY_NODE: u32 = u32::MAX;
+
+/// Bits per axis of `VoxelData::coverage`
+pub const COVERAGE_BITS: u32 = 10;
+pub const COVERAGE_MAX: u32 = (1 << COVERAGE_BITS) - 1;
+/// Coverage of a leaf, filled along every axis
+pub const FULL_COVERAGE: u32 = COVERAGE_MAX | COVERAGE_MAX << COVERAGE_BITS | COVERAGE_MAX << (2 * COVERAGE_BITS);
+
+/// Descriptor set 0 of the rendering shader, indexed by binding number.
+/// Checked against the shader on startup, so keep it in sync with the binding constants above
+pub const RENDER_SET_LAYOUT: [ExpectedBinding; 7] = [
+    ExpectedBinding { kind: DescriptorKind::UniformBuffer, block: Some(ExpectedBlock::Sized(RenderData::STD140.size)) },
+    ExpectedBinding { kind: DescriptorKind::StorageBuffer, block: Some(ExpectedBlock::Array { stride: LayoutRules::Std430.array_stride(VoxelData::STD430) }) },
+    ExpectedBinding { kind: DescriptorKind::StorageImage, block: None },
+    ExpectedBinding { kind: DescriptorKind::StorageBuffer, block: Some(ExpectedBlock::Array { stride: LayoutRules::Std430.array_stride(PaletteEntry::STD430) }) },
+    ExpectedBinding { kind: DescriptorKind::StorageImage, block: None },
+    ExpectedBinding { kind: DescriptorKind::StorageImage, block: None },
+    ExpectedBinding { kind: DescriptorKind::StorageBuffer, block: Some(ExpectedBlock::Array { stride: LayoutRules::Std430.array_stride(CompactNode::STD430) }) }
+];
+
+gpu_struct! {
+    /// std140, contents of the uniform buffer
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct RenderData {
+        pub camera: CameraData,
+        pub light: LightData,
+        pub settings: RenderSettings,
+        pub post: PostSettings
+    }
+}
+
+gpu_struct! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct CameraData {
+        pub pos: Vec3,
+        pub basis: Mat3
+    }
+}
+
+gpu_struct! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct LightData {
+        /// Unit vector pointing towards the sun
+        pub sun_direction: Vec3,
+        /// Linear, may go above one
+        pub sun_color: Vec3,
+        /// Added to every surface, shadowed or not
+        pub ambient_color: Vec3,
+        /// Exponential fog towards the sky colour, zero disables it
+        pub fog_density: f32
+    }
+}
+
+gpu_struct! {
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    pub struct RenderSettings {
+        /// `ViewMode` discriminant
+        pub view_mode: u32,
+        /// Ambient occlusion rays per hit, zero disables it
+        pub ao_samples: u32,
+        /// Occluders further than this are ignored
+        pub ao_radius: f32,
+        /// Frames accumulated by the path tracer since the last reset
+        pub frame_index: u32,
+        pub max_bounces: u32,
+        /// `Antialiasing` discriminant
+        pub antialiasing: u32,
+        /// Non zero moves the samples every frame, the shaded view accumulates then too
+        pub jitter: u32,
+        /// Camera rays stop at nodes smaller than this many pixels, zero disables it
+        pub lod_pixels: f32
+    }
+}
+
+gpu_struct! {
+    /// Read by the post pass only, so changing it does not restart accumulation
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    pub struct PostSettings {
+        /// Linear multiplier applied before tonemapping
+        pub exposure: f32,
+        /// `Tonemapper` discriminant
+        pub tonemapper: u32,
+        /// Non zero when the swapchain format is UNORM and the shader has to encode sRGB itself
+        pub encode_srgb: u32
+    }
+}
+
+gpu_struct! {
+    /// std430, element of the octree storage buffer
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct VoxelData {
+        /// `EMPTY_NODE` where there is no child
+        pub child_indicies: [u32; 8],
+        pub pallete_idx: u32,
+        /// Filled part of the node's silhouette along x, y and z, `COVERAGE_BITS` each from the
+        /// lowest bits up. Set by `lod::aggregate`, leaves are full
+        pub coverage: u32
+    }
+}
+
+impl VoxelData {
+    pub const fn leaf(pallete_idx: u32) -> Self {
+        Self { child_indicies: [EMPTY_NODE; 8], pallete_idx, coverage: FULL_COVERAGE }
+    }
+
+    /// Part of the silhouette along x, y or z that is filled, zero to one
+    pub fn coverage(&self, axis: usize) -> f32 {
+        (self.coverage >> (axis as u32 * COVERAGE_BITS) & COVERAGE_MAX) as f32 / COVERAGE_MAX as f32
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.child_indicies.iter().all(| &child | child == EMPTY_NODE)
     }
 }
-impl From<Mat3> for AlignedMat {
-    fn from(value: Mat3) -> Self {
-        Self (value)
+
+gpu_struct! {
+    /// std430, element of the compact octree storage buffer. Children of a node are stored
+    /// next to each other, interior ones in slot order followed by the palette indices of the
+    /// leaves, six to an element. Empty slots take no space
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct CompactNode {
+        /// Valid mask in bits 0-7, leaf mask in bits 8-15 and palette index in bits 16-31
+        pub masks: u32,
+        /// Index of the child in the lowest valid slot, `EMPTY_NODE` if there are no children
+        pub first_child: u32,
+        /// Same as `VoxelData::coverage`
+        pub coverage: u32
     }
 }
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct CameraData {
-    pub pos: AlignedVec,
-    pub basis: Mat4
+gpu_struct! {
+    /// std430, element of the palette storage buffer, indexed by `VoxelData::pallete_idx`
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct PaletteEntry {
+        /// Linear albedo, reflectance of metals or tint of glass
+        pub color: Vec3,
+        /// One of the `MATERIAL_*` constants
+        pub material: u32,
+        /// Emitted radiance is `color * emission`
+        pub emission: f32,
+        /// Metals only, zero is a perfect mirror
+        pub roughness: f32,
+        /// Glass only, index of refraction
+        pub ior: f32
+    }
 }
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct VoxelData {
-    pub child_indicies: [u32; 8],
-    pub pallete_idx: u32
+impl PaletteEntry {
+    const fn new(color: Vec3, material: u32) -> Self {
+        Self { color, material, emission: 0.0, roughness: 0.0, ior: 1.0 }
+    }
+
+    pub const fn diffuse(color: Vec3) -> Self {
+        Self::new(color, MATERIAL_DIFFUSE)
+    }
+
+    pub const fn emissive(color: Vec3, intensity: f32) -> Self {
+        Self { emission: intensity, ..Self::new(color, MATERIAL_EMISSIVE) }
+    }
+
+    pub const fn metal(color: Vec3, roughness: f32) -> Self {
+        Self { roughness, ..Self::new(color, MATERIAL_METAL) }
+    }
+
+    pub const fn glass(tint: Vec3, ior: f32) -> Self {
+        Self { ior, ..Self::new(tint, MATERIAL_GLASS) }
+    }
 }
 
-//unsafe impl MappableType for VoxelData {}
\ No newline at end of file
+// offsets as declared in rendering_shader.comp
+const _: () = {
+    let render = RenderData::field_offsets(LayoutRules::Std140);
+    assert!(render[0] == 0 && render[1] == 64 && render[2] == 112 && render[3] == 144);
+
+    let camera = CameraData::field_offsets(LayoutRules::Std140);
+    assert!(camera[0] == 0 && camera[1] == 16 && CameraData::STD140.size == 64);
+
+    let light = LightData::field_offsets(LayoutRules::Std140);
+    assert!(light[0] == 0 && light[1] == 16 && light[2] == 32 && light[3] == 44 && LightData::STD140.size == 48);
+
+    let palette = PaletteEntry::field_offsets(LayoutRules::Std430);
+    assert!(palette[1] == 12 && palette[4] == 24 && LayoutRules::Std430.array_stride(PaletteEntry::STD430) == 32);
+
+    let voxel = VoxelData::field_offsets(LayoutRules::Std430);
+    assert!(voxel[0] == 0 && voxel[1] == 32 && voxel[2] == 36 && VoxelData::STD430.size == 40);
+
+    let compact = CompactNode::field_offsets(LayoutRules::Std430);
+    assert!(compact[0] == 0 && compact[1] == 4 && compact[2] == 8 && CompactNode::STD430.size == 12);
+};
+
+
+
+#[test]
+fn test_render_set_layout() {
+    use crate::app::spirv::{self, reflect::ShaderReflection};
+
+    let words = spirv::load(include_bytes!("../../shader/rendering_shader.spv")).unwrap();
+
+    ShaderReflection::reflect(&words).unwrap()
+        .check_set(0, &RENDER_SET_LAYOUT)
+        .unwrap();
+
+    let shader_offsets = | name | ShaderReflection::struct_member_offsets(&words, name)
+        .unwrap()
+        .into_iter()
+        .map(| offset | offset as usize)
+        .collect::<Vec<_>>();
+
+    assert_eq!(shader_offsets("render_data_b"), RenderData::field_offsets(LayoutRules::Std140));
+    assert_eq!(shader_offsets("CameraData"), CameraData::field_offsets(LayoutRules::Std140));
+    assert_eq!(shader_offsets("LightData"), LightData::field_offsets(LayoutRules::Std140));
+    assert_eq!(shader_offsets("RenderSettings"), RenderSettings::field_offsets(LayoutRules::Std140));
+    assert_eq!(shader_offsets("PostSettings"), PostSettings::field_offsets(LayoutRules::Std140));
+    assert_eq!(shader_offsets("VoxelData"), VoxelData::field_offsets(LayoutRules::Std430));
+    assert_eq!(shader_offsets("CompactNode"), CompactNode::field_offsets(LayoutRules::Std430));
+    assert_eq!(shader_offsets("PaletteEntry"), PaletteEntry::field_offsets(LayoutRules::Std430));
+
+    let mut specialized = words.clone();
+    spirv::specialize(&mut specialized, TILE_WIDTH_SPEC_ID, 16).unwrap();
+    spirv::specialize(&mut specialized, TILE_HEIGHT_SPEC_ID, 4).unwrap();
+    spirv::specialize(&mut specialized, PASS_SPEC_ID, PASS_POST).unwrap();
+    spirv::specialize(&mut specialized, NODE_LAYOUT_SPEC_ID, crate::app::config::NodeLayout::Compact as u32).unwrap();
+
+    // every constant differs from its default, so each one patches exactly one word to its value
+    let mut patched: Vec<u32> = words.iter()
+        .zip(&specialized)
+        .filter(| (original, patched) | original != patched)
+        .map(| (_, &patched) | patched)
+        .collect();
+    patched.sort();
+
+    assert_eq!(patched, [1, 1, 4, 16]);
+    ShaderReflection::reflect(&specialized).unwrap()
+        .check_set(0, &RENDER_SET_LAYOUT)
+        .unwrap();
+}
@@ -0,0 +1,132 @@
+//! Octrees from files made elsewhere. Importers return the octree along with the palette its
+//! leaves index into, interior nodes already aggregated
+
+use std::{fmt, fs, io, path::{Path, PathBuf}};
+
+use super::camera::Vec3;
+
+mod heightmap;
+mod image;
+mod mesh;
+mod points;
+mod volume;
+mod voxelize;
+
+pub use self::{heightmap::import_heightmap, mesh::import_mesh, points::import_points, volume::import_volume};
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io { path: PathBuf, error: io::Error },
+    /// File was read, but its content is malformed or unsupported
+    Invalid { path: PathBuf, reason: String }
+}
+
+impl ImportError {
+    fn io(path: &Path, error: io::Error) -> Self {
+        Self::Io { path: path.to_owned(), error }
+    }
+
+    fn invalid(path: &Path, reason: impl Into<String>) -> Self {
+        Self::Invalid { path: path.to_owned(), reason: reason.into() }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            Self::Invalid { path, reason } => write!(f, "{}: {reason}", path.display())
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            Self::Invalid { .. } => None
+        }
+    }
+}
+
+/// Colour of whatever the file gives no colour to
+const DEFAULT_COLOR: Vec3 = Vec3::new(0.6, 0.6, 0.6);
+
+// content of the file along with its lower case extension, which importers pick the format by
+fn read_with_extension(path: &Path) -> Result<(Vec<u8>, Option<String>), ImportError> {
+    let bytes = fs::read(path).map_err(| e | ImportError::io(path, e))?;
+    let extension = path.extension()
+        .and_then(| extension | extension.to_str())
+        .map(str::to_ascii_lowercase);
+
+    Ok((bytes, extension))
+}
+
+// from Z up, a quarter turn around X
+fn z_up_to_world(pos: Vec3) -> Vec3 {
+    Vec3::new(pos.x, -pos.z, pos.y)
+}
+
+// sRGB encoded 0 - 1 values of images and vertex colours to linear ones for the palette
+fn srgb_to_linear(color: Vec3) -> Vec3 {
+    color.map(| c | if c > 0.04045 { ((c + 0.055) / 1.055).powf(2.4) } else { c / 12.92 })
+}
+
+// members of a median cut box along with the channel they spread the most on and by how much
+struct ColorBox {
+    members: Vec<u32>,
+    axis: usize,
+    range: f32
+}
+
+impl ColorBox {
+    fn new(colors: &[Vec3], members: Vec<u32>) -> Self {
+        let (min, max) = members.iter().fold((Vec3::repeat(f32::INFINITY), Vec3::repeat(f32::NEG_INFINITY)), | (min, max), &member | {
+            (min.inf(&colors[member as usize]), max.sup(&colors[member as usize]))
+        });
+        let (axis, range) = (max - min).argmax();
+
+        Self { members, axis, range }
+    }
+}
+
+// median cut, the box spreading the most on any channel is split in half along that channel
+// until there are `count` of them. Returns the average colour of every box and the box of every colour
+fn quantize(colors: &[Vec3], count: usize) -> (Vec<Vec3>, Vec<u32>) {
+    let mut boxes = vec![ColorBox::new(colors, (0..colors.len() as u32).collect())];
+
+    while boxes.len() < count {
+        let (widest, _) = boxes.iter()
+            .enumerate()
+            .max_by(| (_, a), (_, b) | a.range.total_cmp(&b.range))
+            .unwrap();
+
+        // every box is a single colour
+        if boxes[widest].range == 0.0 {
+            break;
+        }
+
+        let ColorBox { mut members, axis, .. } = boxes.swap_remove(widest);
+        members.sort_by(| &a, &b | colors[a as usize][axis].total_cmp(&colors[b as usize][axis]));
+
+        let upper = members.split_off(members.len() / 2);
+        boxes.push(ColorBox::new(colors, members));
+        boxes.push(ColorBox::new(colors, upper));
+    }
+
+    let mut indices = vec![0; colors.len()];
+    let palette = boxes.iter()
+        .enumerate()
+        .map(| (palette_idx, color_box) | {
+            let sum = color_box.members.iter().fold(Vec3::zeros(), | sum, &member | {
+                indices[member as usize] = palette_idx as u32;
+
+                sum + colors[member as usize]
+            });
+
+            sum / color_box.members.len() as f32
+        })
+        .collect();
+
+    (palette, indices)
+}
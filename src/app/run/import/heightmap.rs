@@ -0,0 +1,132 @@
+//! Heightmaps: every pixel becomes a voxel column standing on the bottom of the root, laid out
+//! from the -X -Z corner. Without a colour map columns get the grass, dirt and stone of the terrain
+
+use std::path::Path;
+
+use crate::app::config::TerrainParams;
+
+use super::{super::{camera::Vec3, gpu_shared_data::{PaletteEntry, VoxelData}, terrain}, image::Image, quantize, ImportError};
+
+/// Maps are 2^10 voxels wide at most, larger ones are sampled
+pub const MAX_HEIGHTMAP_DEPTH: u32 = 10;
+
+/// `height_scale` is the height of a white pixel in map widths. The colour map has to be as large
+/// as the heightmap and is reduced to `colors` palette entries
+pub fn import_heightmap(path: &Path, color_map: Option<&Path>, height_scale: f32, colors: u32) -> Result<(Vec<VoxelData>, Vec<PaletteEntry>), ImportError> {
+    let heightmap = Image::load(path)?;
+    let color_map = color_map
+        .map(| color_path | {
+            let color_map = Image::load(color_path)?;
+
+            if (color_map.width, color_map.height) != (heightmap.width, heightmap.height) {
+                let reason = format!(
+                    "color map is {}x{}, but the heightmap is {}x{}",
+                    color_map.width, color_map.height, heightmap.width, heightmap.height
+                );
+
+                return Err(ImportError::invalid(color_path, reason));
+            }
+
+            Ok(color_map)
+        })
+        .transpose()?;
+
+    Ok(build(&heightmap, color_map.as_ref(), height_scale, colors as usize))
+}
+
+fn build(heightmap: &Image, color_map: Option<&Image>, height_scale: f32, colors: usize) -> (Vec<VoxelData>, Vec<PaletteEntry>) {
+    let longest = heightmap.width.max(heightmap.height);
+    let depth = longest.next_power_of_two().ilog2().clamp(1, MAX_HEIGHTMAP_DEPTH);
+    let width = 1u32 << depth;
+    // pixels per column, above one only for maps larger than the limit
+    let step = longest.div_ceil(width);
+
+    // columns past the edge of a map that is not square or not a power of two stay empty
+    let pixel = | column: u32 | {
+        let (x, y) = (column % width * step, column / width * step);
+
+        (x < heightmap.width && y < heightmap.height).then_some((x, y))
+    };
+
+    let heights = (0..width * width)
+        .map(| column | pixel(column).map_or(0, | (x, y) | {
+            let height = heightmap.gray(x, y) * height_scale * width as f32;
+
+            // black pixels still get a voxel of ground
+            (height.round() as u32).clamp(1, width)
+        }))
+        .collect();
+    let params = TerrainParams { depth, sea_level: 0.0, caves: 0.0, ..Default::default() };
+
+    let Some(color_map) = color_map else {
+        return (terrain::generate_from_heights(&params, heights, None), terrain::PALETTE.to_vec());
+    };
+
+    let (columns, column_colors): (Vec<u32>, Vec<Vec3>) = (0..width * width)
+        .filter_map(| column | pixel(column).map(| (x, y) | (column, color_map.color(x, y))))
+        .unzip();
+    let (palette, palette_indices) = quantize(&column_colors, colors);
+
+    let mut column_palette = vec![0; (width * width) as usize];
+    for (column, palette_idx) in columns.into_iter().zip(palette_indices) {
+        column_palette[column as usize] = palette_idx;
+    }
+
+    let palette = palette.into_iter().map(PaletteEntry::diffuse).collect();
+
+    (terrain::generate_from_heights(&params, heights, Some(column_palette)), palette)
+}
+
+
+
+#[test]
+fn test_heightmap() {
+    use super::super::{inspect::inspect, raycast::raycast};
+
+    // a ramp going up along x, 6 wide and 2 deep, and a colour map with three reds and a blue
+    let netpbm = | magic: &str, channels: usize, sample: &dyn Fn(usize, usize) -> u8 | {
+        let mut bytes = format!("{magic} 6 2 255\n").into_bytes();
+        bytes.extend((0..12 * channels).map(| idx | sample(idx / channels % 6, idx % channels)));
+
+        bytes
+    };
+    let path = std::env::temp_dir().join(format!("heightmap-test-{}", std::process::id()));
+    std::fs::create_dir_all(&path).unwrap();
+
+    std::fs::write(path.join("ramp.pgm"), netpbm("P5", 1, &| x, _ | (x * 45 + 15) as u8)).unwrap();
+    std::fs::write(path.join("colors.ppm"), netpbm("P6", 3, &| x, channel | match (x, channel) {
+        (0..=2, 0) => 200 + x as u8 * 20,
+        (3.., 2) => 255,
+        _ => 0
+    })).unwrap();
+    std::fs::write(path.join("small.pgm"), b"P2 2 2 1 0 1 1 0").unwrap();
+
+    // top of the column x voxels from the -X edge, on the first row
+    let column = | octree: &[VoxelData], x: f32 | raycast(octree, Vec3::new(-0.875 + x * 0.25, -3.0, -0.875), Vec3::y());
+
+    let (octree, palette) = import_heightmap(&path.join("ramp.pgm"), None, 1.0, 16).unwrap();
+    let stats = inspect(&octree).unwrap();
+
+    // 8 voxels wide, columns 1 to 8 voxels high with grass on top
+    assert_eq!((stats.max_depth(), palette.len()), (3, terrain::PALETTE.len()));
+    assert!(stats.palette_usage[terrain::GRASS as usize] > 0);
+    assert_eq!(column(&octree, 0.0).unwrap().min_corner().y, 0.75);
+    assert_eq!(column(&octree, 5.0).unwrap().min_corner().y, -1.0);
+    // nothing past the edge of the map
+    assert_eq!(column(&octree, 6.0), None);
+
+    // the reds merge when only two colours are allowed
+    let (octree, palette) = import_heightmap(&path.join("ramp.pgm"), Some(&path.join("colors.ppm")), 1.0, 2).unwrap();
+    let palette_idx = | x: f32 | octree[column(&octree, x).unwrap().node_index as usize].pallete_idx;
+    let red = palette[palette_idx(0.0) as usize].color;
+
+    assert_eq!(palette.len(), 2);
+    assert!(red.x > 0.5 && red.z == 0.0);
+    assert_eq!(palette_idx(2.0), palette_idx(0.0));
+    assert_ne!(palette_idx(3.0), palette_idx(0.0));
+
+    assert!(matches!(import_heightmap(&path.join("ramp.pgm"), Some(&path.join("small.pgm")), 1.0, 2), Err(ImportError::Invalid { .. })));
+    assert!(matches!(import_heightmap(&path.join("missing.png"), None, 1.0, 2), Err(ImportError::Io { .. })));
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
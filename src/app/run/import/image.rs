@@ -0,0 +1,191 @@
+//! Just enough of PNG and of the grayscale and colour netpbm formats for heightmaps and colour maps.
+//! Samples are kept as 0 - 1 floats, so 8 and 16 bit images look the same to the importers
+
+use std::path::Path;
+
+use super::{super::camera::Vec3, read_with_extension, srgb_to_linear, ImportError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    channels: usize,
+    // row by row, channels interleaved
+    samples: Vec<f32>
+}
+
+impl Image {
+    /// Format is picked by the extension: png, pgm, ppm or pnm
+    pub fn load(path: &Path) -> Result<Self, ImportError> {
+        let (bytes, extension) = read_with_extension(path)?;
+
+        let image = match extension.as_deref() {
+            Some("png") => decode_png(&bytes),
+            Some("pgm" | "ppm" | "pnm") => decode_netpbm(&bytes),
+
+            _ => Err("unsupported image format, expected PNG, PGM or PPM".to_owned())
+        };
+
+        image.map_err(| reason | ImportError::invalid(path, reason))
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> &[f32] {
+        let start = (x + y * self.width) as usize * self.channels;
+
+        &self.samples[start..start + self.channels]
+    }
+
+    /// Grayscale value as stored, luminance of the stored values for colour images
+    pub fn gray(&self, x: u32, y: u32) -> f32 {
+        match *self.pixel(x, y) {
+            [r, g, b, ..] => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+            [value, ..] => value,
+            [] => unreachable!()
+        }
+    }
+
+    /// Linear colour, the stored values are sRGB encoded. Grayscale goes to every channel
+    pub fn color(&self, x: u32, y: u32) -> Vec3 {
+        srgb_to_linear(match *self.pixel(x, y) {
+            [r, g, b, ..] => Vec3::new(r, g, b),
+            [value, ..] => Vec3::repeat(value),
+            [] => unreachable!()
+        })
+    }
+}
+
+fn decode_png(bytes: &[u8]) -> Result<Image, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    // indexed colour to RGB, fewer than 8 bits up to 8 and transparency to an alpha channel
+    decoder.set_transformations(png::Transformations::EXPAND);
+
+    let mut reader = decoder.read_info().map_err(| e | e.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(| e | e.to_string())?;
+    let data = &buffer[..info.buffer_size()];
+
+    let samples = match info.bit_depth {
+        png::BitDepth::Sixteen => data.chunks_exact(2)
+            .map(| sample | u16::from_be_bytes([sample[0], sample[1]]) as f32 / u16::MAX as f32)
+            .collect(),
+        _ => data.iter().map(| &sample | sample as f32 / u8::MAX as f32).collect()
+    };
+
+    Ok(Image { width: info.width, height: info.height, channels: info.color_type.samples(), samples })
+}
+
+// whitespace separated header fields, comments run from # to the end of the line
+struct Tokens<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Option<&'a [u8]> {
+        loop {
+            match self.bytes.get(self.pos)? {
+                b'#' => while self.bytes.get(self.pos).is_some_and(| &byte | byte != b'\n') {
+                    self.pos += 1;
+                },
+                byte if byte.is_ascii_whitespace() => self.pos += 1,
+                _ => break
+            }
+        }
+
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(| byte | !byte.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+
+        Some(&self.bytes[start..self.pos])
+    }
+
+    fn number(&mut self) -> Result<u32, String> {
+        self.next()
+            .and_then(| token | std::str::from_utf8(token).ok()?.parse().ok())
+            .ok_or_else(|| "truncated or malformed netpbm image".to_owned())
+    }
+}
+
+// P2 and P5 grayscale, P3 and P6 colour. Samples are 16 bit big endian if the max value is above 255
+fn decode_netpbm(bytes: &[u8]) -> Result<Image, String> {
+    let mut tokens = Tokens { bytes, pos: 0 };
+
+    let (channels, is_ascii) = match tokens.next() {
+        Some(b"P2") => (1, true),
+        Some(b"P3") => (3, true),
+        Some(b"P5") => (1, false),
+        Some(b"P6") => (3, false),
+
+        _ => return Err("not a grayscale or colour netpbm image".to_owned())
+    };
+
+    let width = tokens.number()?;
+    let height = tokens.number()?;
+    let max_value = tokens.number()?;
+
+    if width == 0 || height == 0 {
+        return Err("image is empty".to_owned());
+    }
+    if !(1..=u16::MAX as u32).contains(&max_value) {
+        return Err(format!("max value {max_value} is out of range"));
+    }
+
+    let len = width as usize * height as usize * channels;
+    let samples: Vec<u32> = if is_ascii {
+        (0..len).map(| _ | tokens.number()).collect::<Result<_, _>>()?
+    } else {
+        // a single whitespace byte ends the header
+        let data = bytes.get(tokens.pos + 1..).unwrap_or_default();
+        let bytes_per_sample = if max_value > u8::MAX as u32 { 2 } else { 1 };
+
+        if data.len() < len * bytes_per_sample {
+            return Err("truncated netpbm image".to_owned());
+        }
+
+        match bytes_per_sample {
+            1 => data[..len].iter().map(| &sample | sample as u32).collect(),
+            _ => data[..len * 2].chunks_exact(2).map(| sample | u16::from_be_bytes([sample[0], sample[1]]) as u32).collect()
+        }
+    };
+
+    let samples = samples.into_iter()
+        .map(| sample | (sample as f32 / max_value as f32).min(1.0))
+        .collect();
+
+    Ok(Image { width, height, channels, samples })
+}
+
+
+
+#[test]
+fn test_image_decoding() {
+    // comment in the header, ASCII samples
+    let image = decode_netpbm(b"P2\n# heights\n3 2\n1000\n0 500 1000\n250 750 1000\n").unwrap();
+
+    assert_eq!((image.width, image.height), (3, 2));
+    assert_eq!((image.gray(1, 0), image.gray(0, 1)), (0.5, 0.25));
+
+    // 16 bit binary colour
+    let mut ppm = b"P6 1 1 65535\n".to_vec();
+    ppm.extend([0xff, 0xff, 0, 0, 0x80, 0]);
+    let image = decode_netpbm(&ppm).unwrap();
+
+    assert_eq!(image.color(0, 0).xy(), Vec3::x().xy());
+    assert!((image.color(0, 0).z - 0.214).abs() < 1e-3);
+
+    assert!(decode_netpbm(b"P5 4 4 255\n\x00\x01").is_err());
+    assert!(decode_netpbm(b"P4 1 1\n\x00").is_err());
+
+    // same heights through PNG, 16 bit grayscale
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, 2, 1);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    encoder.write_header().unwrap().write_image_data(&[0x40, 0x00, 0xff, 0xff]).unwrap();
+
+    let image = decode_png(&png).unwrap();
+
+    assert_eq!((image.width, image.height, image.gray(1, 0)), (2, 1, 1.0));
+    assert!((image.gray(0, 0) - 0.25).abs() < 1e-4);
+}
@@ -0,0 +1,330 @@
+//! OBJ and STL meshes. OBJ is Y up and STL Z up, both are turned so their up is the -Y of the world.
+//! Triangles are coloured by their OBJ material, by the average of their OBJ vertex colours or by
+//! the VisCAM colour in the attribute bytes of binary STL
+
+use std::{collections::HashMap, fs, io, path::Path};
+
+use crate::app::config::MeshFill;
+
+use super::{super::{camera::Vec3, gpu_shared_data::{PaletteEntry, VoxelData}}, quantize, read_with_extension, srgb_to_linear, voxelize::voxelize, z_up_to_world, ImportError, DEFAULT_COLOR};
+
+const STL_HEADER_LEN: usize = 84;
+const STL_TRIANGLE_LEN: usize = 50;
+// VisCAM and SolidView set it on facets with a colour in the other 15 bits
+const STL_COLOR_VALID: u16 = 1 << 15;
+
+enum Coloring {
+    /// Colour of every material and material of every triangle
+    Materials { colors: Vec<Vec3>, triangle_materials: Vec<u32> },
+    /// Colour of every triangle, reduced to a palette later
+    Triangles(Vec<Vec3>),
+    Uniform
+}
+
+struct Mesh {
+    /// World orientation, any scale
+    triangles: Vec<[Vec3; 3]>,
+    coloring: Coloring
+}
+
+/// Voxelizes the mesh into a cube of 2^depth voxels along its longest side. Materials get one
+/// palette entry each, vertex and facet colours are reduced to `colors` entries
+pub fn import_mesh(path: &Path, depth: u32, fill: MeshFill, colors: u32) -> Result<(Vec<VoxelData>, Vec<PaletteEntry>), ImportError> {
+    let (bytes, extension) = read_with_extension(path)?;
+
+    let mesh = match extension.as_deref() {
+        Some("obj") => parse_obj(path, &String::from_utf8_lossy(&bytes))?,
+        Some("stl") => parse_stl(&bytes).map_err(| reason | ImportError::invalid(path, reason))?,
+
+        _ => return Err(ImportError::invalid(path, "unsupported mesh format, expected OBJ or STL"))
+    };
+
+    if mesh.triangles.is_empty() {
+        return Err(ImportError::invalid(path, "mesh has no triangles"));
+    }
+
+    let (triangle_palette, palette) = match mesh.coloring {
+        Coloring::Materials { colors, triangle_materials } => (triangle_materials, colors),
+        Coloring::Triangles(triangle_colors) => {
+            let (palette, indices) = quantize(&triangle_colors, colors as usize);
+
+            (indices, palette)
+        },
+        Coloring::Uniform => (vec![0; mesh.triangles.len()], vec![DEFAULT_COLOR])
+    };
+
+    let octree = voxelize(&mesh.triangles, &triangle_palette, depth, fill)
+        .ok_or_else(|| ImportError::invalid(path, "mesh is a single point or has a coordinate that is not a finite number"))?;
+
+    Ok((octree, palette.into_iter().map(PaletteEntry::diffuse).collect()))
+}
+
+// from Y up, rotated half a turn around X
+fn obj_to_world(pos: Vec3) -> Vec3 {
+    Vec3::new(pos.x, -pos.y, -pos.z)
+}
+
+fn parse_floats<const N: usize>(fields: &mut std::str::SplitWhitespace) -> Option<[f32; N]> {
+    let mut values = [0.0; N];
+
+    for value in &mut values {
+        *value = fields.next()?.parse().ok()?;
+    }
+
+    Some(values)
+}
+
+// `newmtl` and `Kd` are all we need of the material libraries
+fn parse_mtl(path: &Path, materials: &mut HashMap<String, Vec3>) -> Result<(), ImportError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        // exporters often ship the OBJ without it, its materials get the default colour then
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(ImportError::io(path, e))
+    };
+    let mut current = None;
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let mut fields = line.split_whitespace();
+
+        match fields.next() {
+            Some("newmtl") => current = Some(fields.collect::<Vec<_>>().join(" ")),
+            Some("Kd") => {
+                let [r, g, b] = parse_floats(&mut fields)
+                    .ok_or_else(|| ImportError::invalid(path, format!("line {}: malformed diffuse colour", line_idx + 1)))?;
+
+                if let Some(name) = &current {
+                    materials.insert(name.clone(), Vec3::new(r, g, b));
+                }
+            },
+            _ => ()
+        }
+    }
+
+    Ok(())
+}
+
+// vertices, faces with any number of corners, vertex colours after the position and materials.
+// texture coordinates, normals, groups and everything else are skipped
+fn parse_obj(path: &Path, text: &str) -> Result<Mesh, ImportError> {
+    let mut positions = Vec::new();
+    let mut vertex_colors = Vec::new();
+    let mut triangles = Vec::new();
+    let mut triangle_vertices = Vec::new();
+
+    let mut material_colors = HashMap::new();
+    let mut used_materials: Vec<String> = Vec::new();
+    let mut current_material = None;
+    let mut triangle_materials = Vec::new();
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let error = | reason: &str | ImportError::invalid(path, format!("line {}: {reason}", line_idx + 1));
+        let mut fields = line.split_whitespace();
+
+        match fields.next() {
+            Some("v") => {
+                let [x, y, z] = parse_floats(&mut fields).ok_or_else(|| error("malformed vertex"))?;
+                positions.push(obj_to_world(Vec3::new(x, y, z)));
+                vertex_colors.push(parse_floats(&mut fields).map(| [r, g, b] | srgb_to_linear(Vec3::new(r, g, b))));
+            },
+            Some("f") => {
+                let corners = fields
+                    .map(| corner | {
+                        // position index is the first of `v/vt/vn`, one based or negative from the end
+                        let idx: i64 = corner.split('/').next()?.parse().ok()?;
+                        let idx = if idx < 0 { positions.len() as i64 + idx } else { idx - 1 };
+
+                        (0..positions.len() as i64).contains(&idx).then_some(idx as usize)
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| error("face refers to a missing vertex"))?;
+
+                if corners.len() < 3 {
+                    return Err(error("face has fewer than three corners"));
+                }
+
+                // fan, fine for the convex faces exporters write
+                for idx in 1..corners.len() - 1 {
+                    let vertices = [corners[0], corners[idx], corners[idx + 1]];
+
+                    triangles.push(vertices.map(| vertex | positions[vertex]));
+                    triangle_vertices.push(vertices);
+                    triangle_materials.push(current_material.unwrap_or(0));
+                }
+            },
+            Some("usemtl") => {
+                let name = fields.collect::<Vec<_>>().join(" ");
+
+                // faces before the first `usemtl` get the default material at index 0
+                if used_materials.is_empty() {
+                    used_materials.push(String::new());
+                }
+
+                current_material = Some(match used_materials.iter().position(| used | *used == name) {
+                    Some(idx) => idx as u32,
+                    None => {
+                        used_materials.push(name);
+                        (used_materials.len() - 1) as u32
+                    }
+                });
+            },
+            Some("mtllib") => for library in fields {
+                parse_mtl(&path.with_file_name(library), &mut material_colors)?;
+            },
+            _ => ()
+        }
+    }
+
+    let coloring = if vertex_colors.iter().any(Option::is_some) {
+        let colors = triangle_vertices.iter()
+            .map(| vertices | vertices.iter().map(| &vertex | vertex_colors[vertex].unwrap_or(DEFAULT_COLOR)).sum::<Vec3>() / 3.0)
+            .collect();
+
+        Coloring::Triangles(colors)
+    } else if !used_materials.is_empty() {
+        let colors = used_materials.iter()
+            .map(| name | material_colors.get(name).copied().unwrap_or(DEFAULT_COLOR))
+            .collect();
+
+        Coloring::Materials { colors, triangle_materials }
+    } else {
+        Coloring::Uniform
+    };
+
+    Ok(Mesh { triangles, coloring })
+}
+
+fn parse_stl(bytes: &[u8]) -> Result<Mesh, String> {
+    let binary_len = bytes.get(80..STL_HEADER_LEN)
+        .map(| count | STL_HEADER_LEN + u32::from_le_bytes(count.try_into().unwrap()) as usize * STL_TRIANGLE_LEN);
+
+    // binary files may start with `solid` too, the length is what tells them apart
+    if binary_len == Some(bytes.len()) {
+        return Ok(parse_binary_stl(&bytes[STL_HEADER_LEN..]));
+    }
+
+    if !bytes.starts_with(b"solid") {
+        return Err("neither an ASCII nor a binary STL file".to_owned());
+    }
+
+    // every three `vertex` lines make a triangle, the facet normals are recomputed anyway
+    let text = String::from_utf8_lossy(bytes);
+    let mut fields = text.split_whitespace();
+    let mut vertices = Vec::new();
+
+    while let Some(field) = fields.next() {
+        if field == "vertex" {
+            let [x, y, z] = parse_floats(&mut fields).ok_or("malformed vertex")?;
+            vertices.push(z_up_to_world(Vec3::new(x, y, z)));
+        }
+    }
+
+    if vertices.len() % 3 != 0 {
+        return Err("facet with other than three vertices".to_owned());
+    }
+
+    let triangles = vertices.chunks_exact(3).map(| vertices | [vertices[0], vertices[1], vertices[2]]).collect();
+
+    Ok(Mesh { triangles, coloring: Coloring::Uniform })
+}
+
+fn parse_binary_stl(data: &[u8]) -> Mesh {
+    let float = | bytes: &[u8], idx: usize | f32::from_le_bytes(bytes[idx * 4..idx * 4 + 4].try_into().unwrap());
+
+    // normal, three vertices and the attribute bytes
+    let (triangles, attributes): (Vec<[Vec3; 3]>, Vec<u16>) = data.chunks_exact(STL_TRIANGLE_LEN)
+        .map(| facet | {
+            let vertices = [1, 2, 3].map(| vertex | z_up_to_world(Vec3::new(float(facet, vertex * 3), float(facet, vertex * 3 + 1), float(facet, vertex * 3 + 2))));
+
+            (vertices, u16::from_le_bytes([facet[48], facet[49]]))
+        })
+        .unzip();
+
+    if attributes.iter().all(| attribute | attribute & STL_COLOR_VALID == 0) {
+        return Mesh { triangles, coloring: Coloring::Uniform };
+    }
+
+    // five bits per channel, blue lowest
+    let colors = attributes.into_iter()
+        .map(| attribute | match attribute & STL_COLOR_VALID {
+            0 => DEFAULT_COLOR,
+            _ => srgb_to_linear(Vec3::new((attribute >> 10 & 31) as f32, (attribute >> 5 & 31) as f32, (attribute & 31) as f32) / 31.0)
+        })
+        .collect();
+
+    Mesh { triangles, coloring: Coloring::Triangles(colors) }
+}
+
+
+
+#[test]
+fn test_mesh_import() {
+    use super::super::inspect::inspect;
+
+    let path = std::env::temp_dir().join(format!("mesh-test-{}", std::process::id()));
+    std::fs::create_dir_all(&path).unwrap();
+
+    // a quad of two materials and a triangle without one, one of the materials is missing in the library
+    std::fs::write(path.join("quad.mtl"), "newmtl red\nKd 1 0 0\n").unwrap();
+    std::fs::write(path.join("quad.obj"), "\
+        mtllib quad.mtl\n\
+        v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\n\
+        f 1 2 5\n\
+        usemtl red\nf 1/1 2/2 3/3\n\
+        usemtl blue\nf -4//1 -3//1 -2//1\n\
+    ").unwrap();
+
+    let mesh = parse_obj(&path.join("quad.obj"), &std::fs::read_to_string(path.join("quad.obj")).unwrap()).unwrap();
+    let Coloring::Materials { colors, triangle_materials } = mesh.coloring else { panic!("materials expected") };
+
+    assert_eq!(mesh.triangles[1], [Vec3::zeros(), Vec3::x(), Vec3::new(1.0, -1.0, 0.0)]);
+    assert_eq!(colors, [DEFAULT_COLOR, Vec3::x(), DEFAULT_COLOR]);
+    assert_eq!(triangle_materials, [0, 1, 2]);
+
+    // vertex colours win over materials
+    let mesh = parse_obj(&path.join("colors.obj"), "v 0 0 0 1 1 1\nv 1 0 0 1 1 1\nv 0 1 0 0 0 0\nusemtl red\nf 1 2 3").unwrap();
+    let Coloring::Triangles(colors) = mesh.coloring else { panic!("vertex colours expected") };
+
+    assert!((colors[0] - Vec3::repeat(2.0 / 3.0)).norm() < 1e-6);
+    assert!(parse_obj(&path.join("broken.obj"), "v 0 0 0\nf 1 2 3").is_err());
+
+    // a missing library leaves every material at the default colour
+    let mesh = parse_obj(&path.join("alone.obj"), "mtllib alone.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3").unwrap();
+    assert!(matches!(mesh.coloring, Coloring::Materials { ref colors, .. } if colors.iter().all(| &color | color == DEFAULT_COLOR)));
+
+    // the same triangle as ASCII and binary STL, the binary one in pure red
+    let ascii = b"solid t\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendloop\nendfacet\nendsolid t\n";
+    let mut binary = b"solid but really binary".to_vec();
+    binary.resize(80, 0);
+    binary.extend(1u32.to_le_bytes());
+    for value in [0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+        binary.extend(value.to_le_bytes());
+    }
+    binary.extend((STL_COLOR_VALID | 31 << 10).to_le_bytes());
+
+    let ascii = parse_stl(ascii).unwrap();
+    let binary = parse_stl(&binary).unwrap();
+
+    assert_eq!(ascii.triangles, binary.triangles);
+    assert_eq!(ascii.triangles[0][2], Vec3::new(0.0, 0.0, 1.0));
+    assert!(matches!(ascii.coloring, Coloring::Uniform));
+    assert!(matches!(binary.coloring, Coloring::Triangles(ref colors) if colors[0] == Vec3::x()));
+
+    // a closed tetrahedron through the whole importer
+    std::fs::write(path.join("tetra.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 3 2\nf 1 2 4\nf 1 4 3\nf 2 3 4\n").unwrap();
+
+    let (surface, palette) = import_mesh(&path.join("tetra.obj"), 4, MeshFill::Surface, 16).unwrap();
+    let (solid, _) = import_mesh(&path.join("tetra.obj"), 4, MeshFill::Parity, 16).unwrap();
+
+    assert_eq!(palette, [PaletteEntry::diffuse(DEFAULT_COLOR)]);
+    // a sixth of the cube around it
+    assert!(inspect(&surface).unwrap().filled_fraction < inspect(&solid).unwrap().filled_fraction);
+    assert!((inspect(&solid).unwrap().filled_fraction - 1.0 / 6.0).abs() < 0.1);
+
+    assert!(matches!(import_mesh(&path.join("quad.mtl"), 4, MeshFill::Surface, 16), Err(ImportError::Invalid { .. })));
+
+    std::fs::write(path.join("nan.obj"), "v nan 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+    assert!(matches!(import_mesh(&path.join("nan.obj"), 4, MeshFill::Surface, 16), Err(ImportError::Invalid { .. })));
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
@@ -0,0 +1,447 @@
+//! PLY and XYZ point clouds, both Z up like most scanners. Points are quantized into voxels, sorted by
+//! Morton code and built bottom up in one pass, so clouds of tens of millions of points fit in memory.
+//! A voxel takes the average colour of its points, colourless clouds are a single grey
+
+use std::{path::Path, str::SplitAsciiWhitespace};
+
+use super::{super::{builder, camera::{UVec3, Vec3}, gpu_shared_data::{PaletteEntry, VoxelData}}, quantize, read_with_extension, srgb_to_linear, z_up_to_world, ImportError, DEFAULT_COLOR};
+
+struct PointCloud {
+    /// World orientation, relative to the first point so coordinates far from the origin keep their precision
+    positions: Vec<Vec3>,
+    /// Linear colour of every point
+    colors: Option<Vec<Vec3>>
+}
+
+/// Quantizes the cloud into a cube of 2^depth voxels along its longest side. Colours are
+/// reduced to `colors` palette entries
+pub fn import_points(path: &Path, depth: u32, colors: u32) -> Result<(Vec<VoxelData>, Vec<PaletteEntry>), ImportError> {
+    let (bytes, extension) = read_with_extension(path)?;
+
+    let cloud = match extension.as_deref() {
+        Some("ply") => parse_ply(&bytes),
+        Some("xyz") => parse_xyz(&String::from_utf8_lossy(&bytes)),
+
+        _ => Err("unsupported point cloud format, expected PLY or XYZ".to_owned())
+    };
+    let cloud = cloud.map_err(| reason | ImportError::invalid(path, reason))?;
+
+    if cloud.positions.is_empty() {
+        return Err(ImportError::invalid(path, "point cloud has no points"));
+    }
+
+    let (octree, palette) = build(&cloud, depth, colors);
+
+    Ok((octree, palette.into_iter().map(PaletteEntry::diffuse).collect()))
+}
+
+// centred in the root like a voxelized mesh. A cloud without extent is a single voxel
+fn build(cloud: &PointCloud, depth: u32, colors: u32) -> (Vec<VoxelData>, Vec<Vec3>) {
+    let (min, max) = cloud.positions.iter().fold((Vec3::repeat(f32::INFINITY), Vec3::repeat(f32::NEG_INFINITY)), | (min, max), pos | {
+        (min.inf(pos), max.sup(pos))
+    });
+    let longest = (max - min).max();
+
+    let width = 1u32 << depth;
+    let scale = if longest > 0.0 { width as f32 / longest } else { 0.0 };
+    let offset = Vec3::repeat(width as f32 / 2.0) - (min + max) / 2.0 * scale;
+
+    let mut keyed: Vec<(u64, u32)> = cloud.positions.iter()
+        .enumerate()
+        .map(| (idx, pos) | {
+            let voxel: UVec3 = (pos * scale + offset).map(| v | (v.max(0.0) as u32).min(width - 1));
+
+            (builder::morton_code(voxel, depth), idx as u32)
+        })
+        .collect();
+    keyed.sort_unstable();
+
+    // points of a voxel are next to each other now
+    let voxels = keyed.chunk_by(| a, b | a.0 == b.0);
+
+    match &cloud.colors {
+        Some(point_colors) => {
+            let (codes, voxel_colors): (Vec<u64>, Vec<Vec3>) = voxels
+                .map(| points | {
+                    let sum = points.iter().fold(Vec3::zeros(), | sum, &(_, idx) | sum + point_colors[idx as usize]);
+
+                    (points[0].0, sum / points.len() as f32)
+                })
+                .unzip();
+            let (palette, indices) = quantize(&voxel_colors, colors as usize);
+
+            (builder::build_sorted(depth, codes.into_iter().zip(indices)), palette)
+        },
+        None => (builder::build_sorted(depth, voxels.map(| points | (points[0].0, 0))), vec![DEFAULT_COLOR])
+    }
+}
+
+// accumulates points relative to the first one, turned to the world
+struct Points {
+    origin: Option<[f64; 3]>,
+    positions: Vec<Vec3>
+}
+
+impl Points {
+    fn push(&mut self, pos: [f64; 3]) -> Result<(), String> {
+        if !pos.iter().all(| v | v.is_finite()) {
+            return Err("point has a coordinate that is not a finite number".to_owned());
+        }
+
+        let origin = *self.origin.get_or_insert(pos);
+        let relative = Vec3::new((pos[0] - origin[0]) as f32, (pos[1] - origin[1]) as f32, (pos[2] - origin[2]) as f32);
+        self.positions.push(z_up_to_world(relative));
+
+        Ok(())
+    }
+}
+
+// `x y z` or `x y z r g b` per line, separated by whitespace or commas, further fields are ignored.
+// Colours are 0 - 1, 0 - 255 or 0 - 65535, whichever range fits all of them
+fn parse_xyz(text: &str) -> Result<PointCloud, String> {
+    let mut points = Points { origin: None, positions: Vec::new() };
+    let mut raw_colors: Option<Vec<Vec3>> = None;
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+
+        let malformed = || format!("line {}: expected x y z with optional r g b", line_idx + 1);
+        let values: Vec<f64> = line.split(| c: char | c.is_ascii_whitespace() || c == ',' || c == ';')
+            .filter(| field | !field.is_empty())
+            .take(6)
+            .map(| field | field.parse().map_err(| _ | malformed()))
+            .collect::<Result<_, _>>()?;
+
+        if values.len() < 3 {
+            return Err(malformed());
+        }
+
+        // the first point decides whether the cloud has colours
+        if points.positions.is_empty() && values.len() == 6 {
+            raw_colors = Some(Vec::new());
+        }
+
+        match (&mut raw_colors, values.len()) {
+            (Some(raw_colors), 6) => raw_colors.push(Vec3::new(values[3] as f32, values[4] as f32, values[5] as f32)),
+            (Some(_), _) => return Err(format!("line {}: point has no colour, unlike the first one", line_idx + 1)),
+            (None, _) => ()
+        }
+
+        points.push([values[0], values[1], values[2]]).map_err(| reason | format!("line {}: {reason}", line_idx + 1))?;
+    }
+
+    let colors = raw_colors.map(| raw_colors | {
+        let brightest = raw_colors.iter().fold(0.0f32, | brightest, color | brightest.max(color.max()));
+        let range = match brightest {
+            b if b <= 1.0 => 1.0,
+            b if b <= u8::MAX as f32 => u8::MAX as f32,
+            _ => u16::MAX as f32
+        };
+
+        raw_colors.into_iter().map(| color | srgb_to_linear((color / range).map(| c | c.clamp(0.0, 1.0)))).collect()
+    });
+
+    Ok(PointCloud { positions: points.positions, colors })
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+
+            _ => return None
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8
+        }
+    }
+
+    // colours of integer types span the whole type, those of floats 0 - 1
+    fn color_range(self) -> f64 {
+        match self {
+            Self::I8 => i8::MAX as f64,
+            Self::U8 => u8::MAX as f64,
+            Self::I16 => i16::MAX as f64,
+            Self::U16 => u16::MAX as f64,
+            Self::I32 => i32::MAX as f64,
+            Self::U32 => u32::MAX as f64,
+            Self::F32 | Self::F64 => 1.0
+        }
+    }
+}
+
+enum Property {
+    Scalar(Scalar),
+    /// Count of items, then the items
+    List { count: Scalar, item: Scalar }
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>
+}
+
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], pos: usize, is_big_endian: bool }
+}
+
+impl Body<'_> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, String> {
+        let truncated = || "truncated or malformed PLY data".to_owned();
+
+        match self {
+            Self::Ascii(tokens) => tokens.next().and_then(| token | token.parse().ok()).ok_or_else(truncated),
+            Self::Binary { data, pos, is_big_endian } => {
+                let size = scalar.size();
+                let bytes = data.get(*pos..*pos + size).ok_or_else(truncated)?;
+                *pos += size;
+
+                // little endian from here on
+                let mut buffer = [0; 8];
+                buffer[..size].copy_from_slice(bytes);
+                if *is_big_endian {
+                    buffer[..size].reverse();
+                }
+
+                let [b0, b1, b2, b3, ..] = buffer;
+                Ok(match scalar {
+                    Scalar::I8 => b0 as i8 as f64,
+                    Scalar::U8 => b0 as f64,
+                    Scalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+                    Scalar::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::F64 => f64::from_le_bytes(buffer)
+                })
+            }
+        }
+    }
+}
+
+// ASCII and binary of either byte order. Points are the x, y and z of the vertex element, colours
+// its red, green and blue. Elements before it are read past, those after it are ignored
+fn parse_ply(bytes: &[u8]) -> Result<PointCloud, String> {
+    let header_end = bytes.windows(10)
+        .position(| window | window == b"end_header")
+        .ok_or_else(|| "not a PLY file or its header has no end".to_owned())?;
+    let header = std::str::from_utf8(&bytes[..header_end]).map_err(| _ | "PLY header is not text".to_owned())?;
+
+    // the header ends with the line break after end_header
+    let body_start = bytes[header_end..].iter()
+        .position(| &byte | byte == b'\n')
+        .map_or(bytes.len(), | pos | header_end + pos + 1);
+
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err("not a PLY file".to_owned());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    for line in lines {
+        let fields: Vec<&str> = line.split_ascii_whitespace().collect();
+        let malformed = || format!("malformed PLY header line \"{line}\"");
+
+        match fields.as_slice() {
+            ["format", name, _version] => format = Some(name.to_string()),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(| _ | malformed())?,
+                properties: Vec::new()
+            }),
+            ["property", "list", count, item, name] => elements.last_mut()
+                .ok_or_else(malformed)?
+                .properties
+                .push((name.to_string(), Property::List {
+                    count: Scalar::parse(count).ok_or_else(malformed)?,
+                    item: Scalar::parse(item).ok_or_else(malformed)?
+                })),
+            ["property", scalar, name] => elements.last_mut()
+                .ok_or_else(malformed)?
+                .properties
+                .push((name.to_string(), Property::Scalar(Scalar::parse(scalar).ok_or_else(malformed)?))),
+            ["comment" | "obj_info", ..] | [] => (),
+
+            _ => return Err(malformed())
+        }
+    }
+
+    let mut body = match format.as_deref() {
+        Some("ascii") => {
+            let text = std::str::from_utf8(&bytes[body_start..]).map_err(| _ | "ASCII PLY data is not text".to_owned())?;
+
+            Body::Ascii(text.split_ascii_whitespace())
+        },
+        Some("binary_little_endian") => Body::Binary { data: &bytes[body_start..], pos: 0, is_big_endian: false },
+        Some("binary_big_endian") => Body::Binary { data: &bytes[body_start..], pos: 0, is_big_endian: true },
+
+        _ => return Err("unknown or missing PLY format".to_owned())
+    };
+
+    let vertex_idx = elements.iter()
+        .position(| element | element.name == "vertex")
+        .ok_or_else(|| "PLY file has no vertex element".to_owned())?;
+
+    // x y z r g b slot of every vertex property
+    let targets: Vec<Option<usize>> = elements[vertex_idx].properties.iter()
+        .map(| (name, _) | ["x", "y", "z", "red", "green", "blue"].iter().position(| target | target == name))
+        .collect();
+
+    for slot in 0..3 {
+        if !targets.contains(&Some(slot)) {
+            return Err("PLY vertices have no x, y and z".to_owned());
+        }
+    }
+
+    let color_ranges: Option<Vec<f64>> = (3..6)
+        .map(| slot | {
+            let property = targets.iter().position(| &target | target == Some(slot))?;
+
+            match elements[vertex_idx].properties[property].1 {
+                Property::Scalar(scalar) => Some(scalar.color_range()),
+                Property::List { .. } => None
+            }
+        })
+        .collect();
+
+    // the count in the header is trusted no further than the data could hold, a vertex takes at
+    // least a byte per property
+    let capacity = elements[vertex_idx].count.min((bytes.len() - body_start) / elements[vertex_idx].properties.len());
+    let mut points = Points { origin: None, positions: Vec::with_capacity(capacity) };
+    let mut colors = color_ranges.as_ref().map(| _ | Vec::with_capacity(capacity));
+
+    // an element without properties takes no data, however large its count
+    for (element_idx, element) in elements.iter().enumerate().take(vertex_idx + 1).filter(| (_, element) | !element.properties.is_empty()) {
+        for _ in 0..element.count {
+            let mut values = [0.0; 6];
+
+            for (property_idx, (_, property)) in element.properties.iter().enumerate() {
+                match *property {
+                    Property::Scalar(scalar) => {
+                        let value = body.read(scalar)?;
+
+                        if let Some(target) = targets[property_idx].filter(| _ | element_idx == vertex_idx) {
+                            values[target] = value;
+                        }
+                    },
+                    Property::List { count, item } => for _ in 0..body.read(count)? as usize {
+                        body.read(item)?;
+                    }
+                }
+            }
+
+            if element_idx != vertex_idx {
+                continue;
+            }
+
+            points.push([values[0], values[1], values[2]])?;
+
+            if let (Some(colors), Some(ranges)) = (&mut colors, &color_ranges) {
+                let color = Vec3::new(
+                    (values[3] / ranges[0]) as f32,
+                    (values[4] / ranges[1]) as f32,
+                    (values[5] / ranges[2]) as f32
+                );
+
+                colors.push(srgb_to_linear(color.map(| c | c.clamp(0.0, 1.0))));
+            }
+        }
+    }
+
+    Ok(PointCloud { positions: points.positions, colors })
+}
+
+
+
+#[test]
+fn test_point_cloud_import() {
+    use super::super::{gpu_shared_data::EMPTY_NODE, inspect::inspect, raycast::raycast};
+
+    // top of the first voxel in a column, a ray going down from above the root
+    let hit = | octree: &[VoxelData], x: f32, z: f32 | raycast(octree, Vec3::new(x, -2.0, z), Vec3::y()).map(| hit | hit.dist - 2.0);
+
+    // the same three points through every format, ASCII PLY with a comment and a face element after the vertices
+    let ascii = b"ply\nformat ascii 1.0\ncomment scan\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+        property uchar red\nproperty uchar green\nproperty uchar blue\nelement face 0\nproperty list uchar int vertex_indices\nend_header\n\
+        0 0 0 255 0 0\n4 0 0 255 0 0\n4 4 4 0 0 255\n".to_vec();
+
+    let mut binary = b"ply\r\nformat binary_big_endian 1.0\r\nelement camera 1\r\nproperty list uchar float view\r\nelement vertex 3\r\n\
+        property double x\r\nproperty double y\r\nproperty double z\r\nproperty ushort red\r\nproperty ushort green\r\nproperty ushort blue\r\nend_header\r\n".to_vec();
+    binary.extend([2, 0, 0, 0, 0, 0, 0, 0, 0]);
+    for (pos, color) in [([0.0f64, 0.0, 0.0], [u16::MAX, 0, 0]), ([4.0, 0.0, 0.0], [u16::MAX, 0, 0]), ([4.0, 4.0, 4.0], [0, 0, u16::MAX])] {
+        binary.extend(pos.iter().flat_map(| v | v.to_be_bytes()));
+        binary.extend(color.iter().flat_map(| v | v.to_be_bytes()));
+    }
+
+    let xyz = "# x y z r g b\n1000000.0 2000000.0 0.0 255 0 0\n1000004.0 2000000.0 0.0 255 0 0\n\n1000004.0 2000004.0 4.0 0 0 255\n";
+
+    for cloud in [parse_ply(&ascii).unwrap(), parse_ply(&binary).unwrap(), parse_xyz(xyz).unwrap()] {
+        let colors = cloud.colors.as_ref().unwrap();
+
+        // Z up turned to -Y up
+        assert_eq!(cloud.positions[2], Vec3::new(4.0, -4.0, 4.0));
+        assert_eq!((colors[0], colors[2]), (Vec3::x(), Vec3::z()));
+
+        let (octree, palette) = build(&cloud, 2, 16);
+
+        assert_eq!(inspect(&octree).unwrap().filled_fraction, 3.0 / 64.0);
+        assert_eq!(palette.len(), 2);
+
+        // the cloud fills the root on x and z, the two red points lie on the bottom layer
+        assert_eq!(hit(&octree, -0.75, -0.75), Some(0.5));
+        assert_eq!(hit(&octree, 0.75, -0.75), Some(0.5));
+        assert_eq!(hit(&octree, 0.75, 0.75), Some(-1.0));
+        assert_eq!(hit(&octree, -0.75, 0.75), None);
+    }
+
+    // points filling every voxel of a uniform cloud merge into a solid root
+    let grid: String = (0..64).map(| idx | format!("{} {} {}\n", idx & 3, idx >> 2 & 3, idx >> 4)).collect();
+    let cloud = parse_xyz(&grid).unwrap();
+    let (octree, palette) = build(&cloud, 2, 16);
+
+    assert_eq!((cloud.colors.is_none(), palette.len()), (true, 1));
+    assert_eq!(octree.len(), 9);
+    assert!(octree[1..].iter().all(| node | node.child_indicies == [EMPTY_NODE; 8]));
+
+    assert!(parse_xyz("1 2\n").is_err());
+    assert!(parse_xyz("1 2 3 4 5 6\n1 2 3\n").is_err());
+    assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n").is_err());
+    assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n0\n").is_err());
+    assert!(parse_ply(b"ply\nformat binary_little_endian 1.0\nelement vertex 999999999999999999\nproperty float x\nproperty float y\nproperty float z\nend_header\n").is_err());
+
+    let cloud = parse_ply(b"ply\nformat ascii 1.0\nelement junk 999999999999999999\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nend_header\n1 2 3\n").unwrap();
+    assert_eq!(cloud.positions.len(), 1);
+}
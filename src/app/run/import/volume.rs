@@ -0,0 +1,246 @@
+//! Scalar volumes such as CT scans, a raw file of samples or a directory of numbered slice images.
+//! Slices are stacked from the bottom up, Z up like the scanners. Samples go through the transfer
+//! function to a palette entry or to empty space, volumes too large for the octree are downsampled
+
+use std::{array, fs, path::Path};
+
+use crate::app::config::{Transfer, VolumeSize};
+
+use super::{super::{builder::{self, Built}, camera::{UVec3, Vec3}, gpu_shared_data::{PaletteEntry, VoxelData}}, image::Image, srgb_to_linear, ImportError, DEFAULT_COLOR};
+
+/// Most voxels along a side are 2^MAX_VOLUME_DEPTH, larger volumes are downsampled
+pub const MAX_VOLUME_DEPTH: u32 = 10;
+
+struct Volume {
+    /// Samples along x, y and z
+    size: UVec3,
+    /// 16 bit whatever the source, x varies fastest and z slowest
+    samples: Vec<u16>
+}
+
+impl Volume {
+    fn sample(&self, x: u32, y: u32, z: u32) -> u16 {
+        let size = self.size.cast::<usize>();
+
+        self.samples[x as usize + (y as usize + z as usize * size.y) * size.x]
+    }
+}
+
+/// A directory is read as slices, anything else as a raw volume of `size` samples with `bits` each
+pub fn import_volume(path: &Path, size: Option<VolumeSize>, bits: u32, transfer: &Transfer) -> Result<(Vec<VoxelData>, Vec<PaletteEntry>), ImportError> {
+    let volume = if path.is_dir() {
+        load_slices(path)?
+    } else {
+        let size = size.ok_or_else(|| ImportError::invalid(path, "size of the raw volume is not given"))?;
+        let bytes = fs::read(path).map_err(| e | ImportError::io(path, e))?;
+
+        parse_raw(&bytes, size, bits).map_err(| reason | ImportError::invalid(path, reason))?
+    };
+
+    let (lookup, palette) = transfer_lookup(transfer);
+
+    Ok((build(&volume, &lookup), palette.into_iter().map(PaletteEntry::diffuse).collect()))
+}
+
+fn parse_raw(bytes: &[u8], size: VolumeSize, bits: u32) -> Result<Volume, String> {
+    let bytes_per_sample = bits as u64 / 8;
+    let expected = (size.width as u64).checked_mul(size.height as u64)
+        .and_then(| len | len.checked_mul(size.depth as u64))
+        .and_then(| len | len.checked_mul(bytes_per_sample))
+        .ok_or_else(|| format!("{}x{}x{} samples are too many", size.width, size.height, size.depth))?;
+
+    if bytes.len() as u64 != expected {
+        return Err(format!(
+            "expected {expected} bytes for {}x{}x{} samples of {bits} bits, the file has {}",
+            size.width, size.height, size.depth, bytes.len()
+        ));
+    }
+
+    let samples = match bytes_per_sample {
+        // spread over the whole 16 bit range, 255 becomes 65535
+        1 => bytes.iter().map(| &sample | sample as u16 * 257).collect(),
+        _ => bytes.chunks_exact(2).map(| sample | u16::from_le_bytes([sample[0], sample[1]])).collect()
+    };
+
+    Ok(Volume { size: UVec3::new(size.width, size.height, size.depth), samples })
+}
+
+// images of the directory in the order of the last number in their names, other files are skipped
+fn load_slices(dir: &Path) -> Result<Volume, ImportError> {
+    let entries = fs::read_dir(dir).map_err(| e | ImportError::io(dir, e))?;
+    let mut slices = Vec::new();
+
+    for entry in entries {
+        let path = entry.map_err(| e | ImportError::io(dir, e))?.path();
+        let is_image = path.extension()
+            .and_then(| extension | extension.to_str())
+            .is_some_and(| extension | ["png", "pgm", "ppm", "pnm"].contains(&extension.to_ascii_lowercase().as_str()));
+        let number = path.file_stem()
+            .and_then(| stem | stem.to_str())
+            .and_then(| stem | {
+                let digits = stem.trim_end_matches(| c: char | !c.is_ascii_digit());
+                let start = digits.trim_end_matches(| c: char | c.is_ascii_digit()).len();
+
+                digits[start..].parse::<u64>().ok()
+            });
+
+        if let (true, Some(number)) = (is_image, number) {
+            slices.push((number, path));
+        }
+    }
+
+    slices.sort();
+
+    if slices.is_empty() {
+        return Err(ImportError::invalid(dir, "directory has no numbered slice images"));
+    }
+    if let Some(pair) = slices.windows(2).find(| pair | pair[0].0 == pair[1].0) {
+        return Err(ImportError::invalid(&pair[1].1, format!("another slice is numbered {} too", pair[0].0)));
+    }
+
+    let mut volume = Volume { size: UVec3::zeros(), samples: Vec::new() };
+
+    for (_, path) in &slices {
+        let image = Image::load(path)?;
+
+        if volume.samples.is_empty() {
+            volume.size = UVec3::new(image.width, image.height, slices.len() as u32);
+            volume.samples.reserve(volume.size.cast::<usize>().product());
+        } else if (image.width, image.height) != (volume.size.x, volume.size.y) {
+            return Err(ImportError::invalid(path, format!("slice is {}x{}, unlike the first one", image.width, image.height)));
+        }
+
+        for y in 0..image.height {
+            for x in 0..image.width {
+                volume.samples.push((image.gray(x, y) * u16::MAX as f32).round() as u16);
+            }
+        }
+    }
+
+    Ok(volume)
+}
+
+// palette entry of every sample value, None for empty space, and the palette
+fn transfer_lookup(transfer: &Transfer) -> (Vec<Option<u32>>, Vec<Vec3>) {
+    match transfer {
+        Transfer::Threshold(threshold) => {
+            let lookup = (0..=u16::MAX).map(| value | (value >= *threshold).then_some(0)).collect();
+
+            (lookup, vec![DEFAULT_COLOR])
+        },
+        Transfer::Stops(stops) => {
+            let lookup = (0..=u16::MAX)
+                .map(| value | stops.iter().rposition(| stop | stop.value <= value).map(| stop | stop as u32))
+                .collect();
+            let palette = stops.iter()
+                .map(| stop | srgb_to_linear(Vec3::from(stop.color.map(| c | c as f32 / u8::MAX as f32))))
+                .collect();
+
+            (lookup, palette)
+        }
+    }
+}
+
+struct VolumeBuilder<'a> {
+    volume: &'a Volume,
+    lookup: &'a [Option<u32>],
+    /// Samples per voxel along every axis, above one only for volumes larger than the limit
+    step: u32,
+    /// Voxels the volume covers, centred in the root. World axes, so y is the height
+    min: UVec3,
+    max: UVec3
+}
+
+impl VolumeBuilder<'_> {
+    fn voxel(&self, pos: UVec3) -> Built {
+        // volume z grows up, the world y down
+        let local = pos - self.min;
+        let (x, y, z) = (local.x * self.step, local.z * self.step, (self.max.y - 1 - pos.y) * self.step);
+
+        match self.lookup[self.volume.sample(x, y, z) as usize] {
+            Some(palette_idx) => Built::Solid(palette_idx),
+            None => Built::Empty
+        }
+    }
+
+    fn build(&self, nodes: &mut Vec<VoxelData>, min: UVec3, size: u32) -> Built {
+        let max = min.add_scalar(size);
+
+        if (0..3).any(| axis | max[axis] <= self.min[axis] || min[axis] >= self.max[axis]) {
+            return Built::Empty;
+        }
+
+        if size == 1 {
+            return self.voxel(min);
+        }
+
+        let half = size / 2;
+        let children = array::from_fn(| slot | self.build(nodes, builder::child_min(min, half, slot), half));
+
+        builder::combine(nodes, children)
+    }
+}
+
+// the longest side gets 2^depth voxels, fewer than one per sample only past the limit
+fn build(volume: &Volume, lookup: &[Option<u32>]) -> Vec<VoxelData> {
+    let longest = volume.size.max();
+    let depth = longest.next_power_of_two().trailing_zeros().clamp(1, MAX_VOLUME_DEPTH);
+    let width = 1 << depth;
+    let step = longest.div_ceil(width);
+
+    // volume x, y and z to world x, z and y
+    let extent = volume.size.map(| samples | samples.div_ceil(step));
+    let extent = UVec3::new(extent.x, extent.z, extent.y);
+    let min = (UVec3::repeat(width) - extent) / 2;
+
+    let builder = VolumeBuilder { volume, lookup, step, min, max: min + extent };
+
+    builder::build(| nodes | builder.build(nodes, UVec3::zeros(), width))
+}
+
+
+
+#[test]
+fn test_volume_import() {
+    use crate::app::config::TransferStop;
+
+    use super::super::inspect::inspect;
+
+    // 4x4x2, the lower slice a gradient along x, the upper one full
+    let mut bytes: Vec<u8> = (0..16).map(| idx | (idx % 4 * 85) as u8).collect();
+    bytes.extend([255; 16]);
+
+    let volume = parse_raw(&bytes, VolumeSize { width: 4, height: 4, depth: 2 }, 8).unwrap();
+    let (lookup, palette) = transfer_lookup(&Transfer::Threshold(u16::MAX / 2 + 1));
+    let octree = build(&volume, &lookup);
+
+    // half of the lower slice and all of the upper one, each a quarter of the root high
+    assert_eq!((palette.len(), inspect(&octree).unwrap().filled_fraction), (1, (8.0 + 16.0) / 64.0));
+
+    // the same as 16 bit slices, with two stops
+    let dir = std::env::temp_dir().join(format!("volume-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    for (number, samples) in [(9, &bytes[16..]), (2, &bytes[..16])] {
+        let pgm: String = format!("P2 4 4 65535\n{}\n", samples.iter().map(| &s | (s as u32 * 257).to_string()).collect::<Vec<_>>().join(" "));
+        fs::write(dir.join(format!("slice{number:03}.pgm")), pgm).unwrap();
+    }
+    fs::write(dir.join("notes.txt"), "not a slice").unwrap();
+
+    let stops = Transfer::Stops(vec![TransferStop { value: 20000, color: [255, 0, 0] }, TransferStop { value: 60000, color: [255; 3] }]);
+    let (octree, palette) = import_volume(&dir, None, 8, &stops).unwrap();
+    let stats = inspect(&octree).unwrap();
+
+    // 170 and 85 are red, 255 white
+    assert_eq!(palette.len(), 2);
+    assert_eq!(stats.filled_fraction, (12.0 + 16.0) / 64.0);
+    assert_eq!(stats.palette_usage.iter().filter(| &&count | count > 0).count(), 2);
+
+    fs::write(dir.join("slice010.png"), b"broken").unwrap();
+    assert!(import_volume(&dir, None, 8, &stops).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(parse_raw(&bytes, VolumeSize { width: 4, height: 4, depth: 2 }, 16).is_err());
+    assert!(parse_raw(&bytes, VolumeSize { width: u32::MAX, height: u32::MAX, depth: u32::MAX }, 16).is_err());
+    assert!(import_volume(Path::new("head.raw"), None, 8, &stops).is_err());
+}
@@ -0,0 +1,239 @@
+//! Triangles to voxels, top down. A node no triangle touches is entirely inside or outside, so only
+//! the surface is split down to single voxels and the inside is filled with nodes as large as possible
+
+use std::array;
+
+use crate::app::config::MeshFill;
+
+use super::super::{builder::{self, Built}, camera::{UVec3, Vec2, Vec3}, gpu_shared_data::VoxelData};
+
+// where a triangle crosses the vertical line through the centre of a column
+struct Crossing {
+    y: f32,
+    /// Which way the triangle faces along the line, -1 or 1
+    winding: i32,
+    palette_idx: u32
+}
+
+struct Voxelizer<'a> {
+    /// In voxels of the finest layer
+    triangles: Vec<[Vec3; 3]>,
+    triangle_palette: &'a [u32],
+    width: u32,
+    fill: MeshFill,
+    /// Lowest y first, indexed by x + z * width. Empty for `MeshFill::Surface`
+    crossings: Vec<Vec<Crossing>>
+}
+
+// separating axis test by Akenine-Möller: the box normals, the triangle normal and the cross
+// products of the box normals with the triangle edges. Touching counts as overlapping
+fn triangle_overlaps_box(center: Vec3, half_size: f32, triangle: &[Vec3; 3]) -> bool {
+    let vertices = triangle.map(| vertex | vertex - center);
+    let edges = [vertices[1] - vertices[0], vertices[2] - vertices[1], vertices[0] - vertices[2]];
+
+    let is_separating = | axis: Vec3 | {
+        let projections = vertices.map(| vertex | vertex.dot(&axis));
+        let radius = half_size * axis.abs().sum();
+
+        projections.iter().all(| &p | p > radius) || projections.iter().all(| &p | p < -radius)
+    };
+
+    let box_normals = [Vec3::x(), Vec3::y(), Vec3::z()];
+
+    !(box_normals.into_iter().any(is_separating)
+        || is_separating(edges[0].cross(&edges[1]))
+        || box_normals.into_iter().any(| normal | edges.iter().any(| edge | is_separating(normal.cross(edge)))))
+}
+
+// twice the signed area of the triangle a b p, positive if counter clockwise
+fn edge_function(a: Vec2, b: Vec2, p: Vec2) -> f32 {
+    (b - a).perp(&(p - a))
+}
+
+// points exactly on an edge shared by two triangles belong to only one of them,
+// otherwise a ray through it would cross the surface twice
+fn owns_edge(a: Vec2, b: Vec2) -> bool {
+    let dir = b - a;
+
+    dir.y < 0.0 || (dir.y == 0.0 && dir.x > 0.0)
+}
+
+impl Voxelizer<'_> {
+    fn compute_crossings(&mut self) {
+        self.crossings = (0..self.width * self.width).map(| _ | Vec::new()).collect();
+
+        for (triangle, &palette_idx) in self.triangles.iter().zip(self.triangle_palette) {
+            let [a, b, c] = triangle.map(| vertex | vertex.xz());
+            let area = edge_function(a, b, c);
+
+            // parallel to the lines
+            if area == 0.0 {
+                continue;
+            }
+
+            // counter clockwise in the XZ plane, the sign is kept for the winding number
+            let (b, c, winding) = if area > 0.0 { (b, c, 1) } else { (c, b, -1) };
+            let area = area.abs();
+            let min = a.inf(&b).inf(&c);
+            let max = a.sup(&b).sup(&c);
+
+            // columns with their centre inside the bounds
+            let first = (min - Vec2::repeat(0.5)).map(| v | v.ceil().max(0.0) as u32);
+            let last = (max - Vec2::repeat(0.5)).map(| v | v.floor().min(self.width as f32 - 1.0) as i64);
+
+            for z in first.y as i64..=last.y {
+                for x in first.x as i64..=last.x {
+                    let p = Vec2::new(x as f32 + 0.5, z as f32 + 0.5);
+                    let weights = [(b, c), (c, a), (a, b)].map(| (from, to) | (edge_function(from, to, p), owns_edge(from, to)));
+
+                    if !weights.iter().all(| &(weight, owned) | weight > 0.0 || (weight == 0.0 && owned)) {
+                        continue;
+                    }
+
+                    // weights follow the swapped corners, so they are matched with the y of the same ones
+                    let ys = if winding > 0 { [triangle[0].y, triangle[1].y, triangle[2].y] } else { [triangle[0].y, triangle[2].y, triangle[1].y] };
+                    let y = weights.iter().zip(ys).map(| (&(weight, _), y) | weight * y).sum::<f32>() / area;
+
+                    self.crossings[(x + z * self.width as i64) as usize].push(Crossing { y, winding, palette_idx });
+                }
+            }
+        }
+
+        for column in &mut self.crossings {
+            column.sort_by(| a, b | a.y.total_cmp(&b.y));
+        }
+    }
+
+    // region without triangles, inside if the line up from its centre crosses the surface the right way.
+    // takes the colour of the closest crossing
+    fn fill_region(&self, min: UVec3, size: u32) -> Built {
+        if self.fill == MeshFill::Surface {
+            return Built::Empty;
+        }
+
+        let center_y = min.y as f32 + size as f32 / 2.0;
+        let above = self.crossings[(min.x + min.z * self.width) as usize].iter()
+            .take_while(| crossing | crossing.y < center_y);
+
+        let (count, winding, closest) = above.fold((0, 0, None), | (count, winding, _), crossing | {
+            (count + 1, winding + crossing.winding, Some(crossing.palette_idx))
+        });
+
+        let is_inside = match self.fill {
+            MeshFill::Parity => count % 2 == 1,
+            _ => winding != 0
+        };
+
+        match closest {
+            Some(palette_idx) if is_inside => Built::Solid(palette_idx),
+            _ => Built::Empty
+        }
+    }
+
+    fn build(&self, nodes: &mut Vec<VoxelData>, min: UVec3, size: u32, candidates: &[u32]) -> Built {
+        let half_size = size as f32 / 2.0;
+        let center = min.cast::<f32>() + Vec3::repeat(half_size);
+
+        let touching: Vec<u32> = candidates.iter()
+            .copied()
+            .filter(| &triangle | triangle_overlaps_box(center, half_size, &self.triangles[triangle as usize]))
+            .collect();
+
+        if touching.is_empty() {
+            return self.fill_region(min, size);
+        }
+
+        if size == 1 {
+            return Built::Solid(self.triangle_palette[touching[0] as usize]);
+        }
+
+        let half = size / 2;
+        let children = array::from_fn(| slot | self.build(nodes, builder::child_min(min, half, slot), half, &touching));
+
+        builder::combine(nodes, children)
+    }
+}
+
+/// The mesh is centred in the root and scaled so its longest side is 2^depth voxels.
+/// None if it has no extent at all or a coordinate is not finite
+pub fn voxelize(triangles: &[[Vec3; 3]], triangle_palette: &[u32], depth: u32, fill: MeshFill) -> Option<Vec<VoxelData>> {
+    // the bounds below would skip NaN, `inf` and `sup` ignore it
+    if !triangles.iter().flatten().all(| vertex | vertex.iter().all(| v | v.is_finite())) {
+        return None;
+    }
+
+    let (min, max) = triangles.iter().flatten().fold((Vec3::repeat(f32::INFINITY), Vec3::repeat(f32::NEG_INFINITY)), | (min, max), vertex | {
+        (min.inf(vertex), max.sup(vertex))
+    });
+    let longest = (max - min).max();
+
+    if !(longest.is_finite() && longest > 0.0) {
+        return None;
+    }
+
+    let width = 1u32 << depth;
+    let scale = width as f32 / longest;
+    let offset = Vec3::repeat(width as f32 / 2.0) - (min + max) / 2.0 * scale;
+
+    let mut voxelizer = Voxelizer {
+        triangles: triangles.iter().map(| triangle | triangle.map(| vertex | vertex * scale + offset)).collect(),
+        triangle_palette,
+        width,
+        fill,
+        crossings: Vec::new()
+    };
+
+    if fill != MeshFill::Surface {
+        voxelizer.compute_crossings();
+    }
+
+    let all: Vec<u32> = (0..triangles.len() as u32).collect();
+
+    Some(builder::build(| nodes | voxelizer.build(nodes, UVec3::zeros(), width, &all)))
+}
+
+
+
+#[test]
+fn test_voxelization() {
+    use super::super::inspect::inspect;
+
+    // outward facing, counter clockwise seen from outside
+    let cube = | min: Vec3, max: Vec3 | {
+        let corner = | idx: usize | Vec3::new(
+            if idx & 1 != 0 { max.x } else { min.x },
+            if idx & 2 != 0 { max.y } else { min.y },
+            if idx & 4 != 0 { max.z } else { min.z }
+        );
+        let quads = [[0, 4, 6, 2], [1, 3, 7, 5], [0, 1, 5, 4], [2, 6, 7, 3], [0, 2, 3, 1], [4, 5, 7, 6]];
+
+        quads.into_iter()
+            .flat_map(| [a, b, c, d] | [[corner(a), corner(b), corner(c)], [corner(a), corner(c), corner(d)]])
+            .collect::<Vec<_>>()
+    };
+    let filled = | triangles: &[[Vec3; 3]], fill | inspect(&voxelize(triangles, &vec![0; triangles.len()], 4, fill).unwrap()).unwrap().filled_fraction;
+
+    // the cube is the whole root, 16 voxels wide
+    let single = cube(-Vec3::repeat(1.0), Vec3::repeat(1.0));
+
+    assert_eq!(filled(&single, MeshFill::Surface), 1.0 - 2744.0 / 4096.0);
+    assert_eq!(filled(&single, MeshFill::Parity), 1.0);
+    assert_eq!(filled(&single, MeshFill::Winding), 1.0);
+
+    // two overlapping cubes, parity counts the overlap as outside
+    let mut overlapping = cube(Vec3::repeat(-1.0), Vec3::repeat(0.6));
+    overlapping.extend(cube(Vec3::repeat(-0.6), Vec3::repeat(1.0)));
+
+    assert!(filled(&overlapping, MeshFill::Parity) < filled(&overlapping, MeshFill::Winding));
+    assert!(filled(&overlapping, MeshFill::Surface) < filled(&overlapping, MeshFill::Parity));
+
+    // surface voxels take the palette of a triangle they touch, the inside that of the closest crossing
+    let mut palette = vec![0; single.len()];
+    palette[8] = 3;
+    palette[9] = 3;
+    let octree = voxelize(&single, &palette, 3, MeshFill::Parity).unwrap();
+
+    assert_eq!(inspect(&octree).unwrap().palette_usage.iter().filter(| &&count | count > 0).count(), 2);
+
+    assert_eq!(voxelize(&[[Vec3::zeros(); 3]], &[0], 3, MeshFill::Surface), None);
+}
@@ -0,0 +1,152 @@
+//! Octree statistics for the `inspect` subcommand.
+//! Shared DAG nodes are counted once as stored and once per reference as part of the tree
+
+use std::{collections::HashMap, fmt};
+
+use crate::app::config::NodeLayout;
+
+use super::{compact, gpu_layout::{GpuType, LayoutRules}, gpu_shared_data::{CompactNode, VoxelData, EMPTY_NODE}, validate::{self, ValidationError}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelStats {
+    /// Distinct nodes in the buffer
+    pub stored: usize,
+    /// Nodes of the tree the DAG stands for
+    pub expanded: u64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OctreeStats {
+    /// Reachable from the root, indexed by depth
+    pub levels: Vec<LevelStats>,
+    /// Whole buffer, unreachable nodes included
+    pub buffer_nodes: usize,
+    /// Nodes without children, as part of the tree
+    pub leaves: u64,
+    /// Part of the root cube covered by leaves, 0 - 1
+    pub filled_fraction: f64,
+    /// Leaves using each palette entry, indexed by palette index
+    pub palette_usage: Vec<u64>,
+    /// Size of the octree buffer in each layout
+    pub memory: Vec<(NodeLayout, usize)>
+}
+
+impl OctreeStats {
+    pub fn max_depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    pub fn stored_nodes(&self) -> usize {
+        self.levels.iter().map(| level | level.stored).sum()
+    }
+
+    pub fn expanded_nodes(&self) -> u64 {
+        self.levels.iter().map(| level | level.expanded).sum()
+    }
+}
+
+impl fmt::Display for OctreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "nodes: {} reachable of {} stored, {} as a tree", self.stored_nodes(), self.buffer_nodes, self.expanded_nodes())?;
+        writeln!(f, "max depth: {}", self.max_depth())?;
+
+        writeln!(f, "nodes per level, stored / as a tree:")?;
+        for (depth, level) in self.levels.iter().enumerate() {
+            writeln!(f, "    {depth:>2}: {} / {}", level.stored, level.expanded)?;
+        }
+
+        writeln!(f, "leaves: {}, {:.2}% of the volume filled", self.leaves, self.filled_fraction * 100.0)?;
+
+        writeln!(f, "palette usage:")?;
+        for (idx, &count) in self.palette_usage.iter().enumerate().filter(| (_, count) | **count != 0) {
+            writeln!(f, "    {idx:>3}: {count} leaves, {:.1}%", count as f64 / self.leaves as f64 * 100.0)?;
+        }
+
+        writeln!(f, "memory:")?;
+        for (layout, bytes) in &self.memory {
+            writeln!(f, "    {:>7}: {:.1} KiB", layout.to_string(), *bytes as f32 / 1024.0)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Validates first, the statistics walk assumes a well formed octree
+pub fn inspect(octree: &[VoxelData]) -> Result<OctreeStats, Vec<ValidationError>> {
+    validate::validate(octree)?;
+
+    let mut levels = Vec::new();
+    let mut leaves = 0;
+    let mut filled_fraction = 0.0;
+    let mut palette_usage = Vec::new();
+
+    // every node is reached at a single depth, so walking level by level visits it once.
+    // value is how many times the node appears in the tree
+    let mut level = HashMap::from([(0u32, 1u64)]);
+
+    while !level.is_empty() {
+        let depth = levels.len() as i32;
+        let mut next_level = HashMap::new();
+
+        for (&index, &count) in &level {
+            let node = &octree[index as usize];
+
+            if node.is_leaf() {
+                leaves += count;
+                filled_fraction += count as f64 * 0.125f64.powi(depth);
+
+                if palette_usage.len() <= node.pallete_idx as usize {
+                    palette_usage.resize(node.pallete_idx as usize + 1, 0);
+                }
+                palette_usage[node.pallete_idx as usize] += count;
+            }
+
+            for &child in node.child_indicies.iter().filter(| &&child | child != EMPTY_NODE) {
+                *next_level.entry(child).or_insert(0) += count;
+            }
+        }
+
+        levels.push(LevelStats { stored: level.len(), expanded: level.values().sum() });
+        level = next_level;
+    }
+
+    let memory = vec![
+        (NodeLayout::Full, octree.len() * LayoutRules::Std430.array_stride(VoxelData::STD430)),
+        (NodeLayout::Compact, compact::from_voxel_data(octree).len() * LayoutRules::Std430.array_stride(CompactNode::STD430))
+    ];
+
+    Ok(OctreeStats { levels, buffer_nodes: octree.len(), leaves, filled_fraction, palette_usage, memory })
+}
+
+
+
+#[test]
+fn test_inspect() {
+    use super::{dag, voxel_data_generator::generate_tree};
+
+    let octree = generate_tree(3);
+    let stats = inspect(&octree).unwrap();
+
+    // five children per node
+    assert_eq!(stats.levels, [
+        LevelStats { stored: 1, expanded: 1 },
+        LevelStats { stored: 5, expanded: 5 },
+        LevelStats { stored: 25, expanded: 25 }
+    ]);
+    assert_eq!((stats.max_depth(), stats.leaves, stats.buffer_nodes), (2, 25, 31));
+    assert!((stats.filled_fraction - 25.0 / 64.0).abs() < 1e-9);
+    assert_eq!(stats.palette_usage.iter().sum::<u64>(), 25);
+
+    // same tree, fewer stored nodes
+    let dag_stats = inspect(&dag::compress(&octree).0).unwrap();
+
+    assert_eq!(dag_stats.expanded_nodes(), stats.expanded_nodes());
+    assert_eq!((dag_stats.leaves, &dag_stats.palette_usage), (stats.leaves, &stats.palette_usage));
+    assert!((dag_stats.filled_fraction - stats.filled_fraction).abs() < 1e-9);
+    assert!(dag_stats.stored_nodes() < stats.stored_nodes());
+    assert!(dag_stats.memory.iter().all(| &(_, bytes) | bytes > 0));
+
+    let mut broken = octree.clone();
+    broken[3].child_indicies[0] = 0;
+    assert!(inspect(&broken).is_err());
+}
@@ -0,0 +1,76 @@
+//! Sun and sky, the only light sources for now.
+//! World up is -Y, screen rows go down along +Y
+
+use std::f32::consts::{FRAC_PI_2, TAU};
+
+use crate::app::config::Config;
+
+use super::{camera::Vec3, gpu_shared_data::LightData};
+
+// keeps the sun away from the zenith, where azimuth stops doing anything
+const MAX_ELEVATION: f32 = FRAC_PI_2 * 0.99;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sun {
+    /// Radians around the up axis, zero points along +Z
+    pub azimuth: f32,
+    /// Radians above the horizon
+    pub elevation: f32,
+    pub color: Vec3,
+    pub ambient: Vec3,
+    /// Fog takes the colour of the sky, so it lives here too
+    pub fog_density: f32
+}
+
+impl Sun {
+    pub fn from_config(config: &Config) -> Self {
+        let [azimuth, elevation] = config.sun_angles.0;
+
+        Self {
+            azimuth: azimuth.to_radians(),
+            elevation: elevation.to_radians().clamp(-MAX_ELEVATION, MAX_ELEVATION),
+            color: config.sun_color.0.into(),
+            ambient: config.ambient_color.0.into(),
+            fog_density: config.fog_density
+        }
+    }
+
+    pub fn rotate(&mut self, azimuth: f32, elevation: f32) {
+        self.azimuth = (self.azimuth + azimuth).rem_euclid(TAU);
+        self.elevation = (self.elevation + elevation).clamp(-MAX_ELEVATION, MAX_ELEVATION);
+    }
+
+    /// Unit vector pointing towards the sun
+    pub fn direction(&self) -> Vec3 {
+        let (sin_az, cos_az) = self.azimuth.sin_cos();
+        let (sin_el, cos_el) = self.elevation.sin_cos();
+
+        Vec3::new(cos_el * sin_az, -sin_el, cos_el * cos_az)
+    }
+
+    pub fn build_light_data(&self) -> LightData {
+        LightData {
+            sun_direction: self.direction(),
+            sun_color: self.color,
+            ambient_color: self.ambient,
+            fog_density: self.fog_density
+        }
+    }
+}
+
+
+
+#[test]
+fn test_sun_direction() {
+    let mut sun = Sun { azimuth: 0.0, elevation: 0.0, color: Vec3::repeat(1.0), ambient: Vec3::zeros(), fog_density: 0.0 };
+
+    assert!((sun.direction() - Vec3::z()).norm() < 1e-6);
+
+    sun.rotate(FRAC_PI_2, 0.0);
+    assert!((sun.direction() - Vec3::x()).norm() < 1e-6);
+
+    // can't go past the zenith
+    sun.rotate(0.0, 10.0);
+    assert_eq!(sun.elevation, MAX_ELEVATION);
+    assert!(sun.direction().y < -0.99);
+}
@@ -0,0 +1,198 @@
+//! Level of detail aggregates. Every interior node gets the palette entry covering most of its
+//! volume and how much of its silhouette along each axis is filled, so a walk may stop at a node
+//! once it is smaller than a pixel, shade it, and let as many rays through as its children would
+
+use std::collections::HashMap;
+
+use super::gpu_shared_data::{VoxelData, COVERAGE_BITS, COVERAGE_MAX, EMPTY_NODE};
+
+// (palette index, part of the node volume it covers)
+type Histogram = Vec<(u32, f64)>;
+
+struct Aggregator<'a> {
+    octree: &'a mut [VoxelData],
+    // parents of every reachable node which have not aggregated it yet
+    pending_parents: Vec<u32>,
+    // shared DAG nodes are aggregated once, their histogram is kept until the last parent takes it
+    shared: HashMap<u32, Histogram>
+}
+
+impl Aggregator<'_> {
+    fn aggregate(&mut self, index: u32) -> Histogram {
+        let pending = &mut self.pending_parents[index as usize];
+
+        if let Some(histogram) = self.shared.get(&index) {
+            *pending -= 1;
+
+            return if *pending == 0 { self.shared.remove(&index).unwrap() } else { histogram.clone() };
+        }
+
+        let node = self.octree[index as usize];
+
+        // only the root gets here as a leaf, parents add their leaf children themselves
+        if node.is_leaf() {
+            return vec![(node.pallete_idx, 1.0)];
+        }
+
+        let mut histogram = Histogram::new();
+
+        for &child in node.child_indicies.iter().filter(| &&child | child != EMPTY_NODE) {
+            let child_node = self.octree[child as usize];
+
+            // a child is an eighth of its parent. Most nodes of a large octree are leaves, which
+            // are cheaper to look at than to give a histogram of their own
+            if child_node.is_leaf() {
+                add(&mut histogram, child_node.pallete_idx, 1.0 / 8.0);
+            } else {
+                for (palette_idx, part) in self.aggregate(child) {
+                    add(&mut histogram, palette_idx, part / 8.0);
+                }
+            }
+        }
+
+        // lowest index wins ties, so equal subtrees get equal aggregates and stay mergeable
+        let (dominant, _) = histogram.iter()
+            .copied()
+            .max_by(| a, b | a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)))
+            .unwrap();
+        self.octree[index as usize].pallete_idx = dominant;
+        self.octree[index as usize].coverage = silhouette_coverage(self.octree, &node);
+
+        let pending = &mut self.pending_parents[index as usize];
+
+        if *pending > 1 {
+            *pending -= 1;
+            self.shared.insert(index, histogram.clone());
+        }
+
+        histogram
+    }
+}
+
+// children are aggregated already. The two children behind each other along an axis are taken
+// to fill their silhouettes independently of each other
+fn silhouette_coverage(octree: &[VoxelData], node: &VoxelData) -> u32 {
+    // slot bit of x, y and z
+    const AXIS_BITS: [usize; 3] = [1, 4, 2];
+
+    (0..3).fold(0, | coverage, axis | {
+        let child_coverage = | slot: usize | match node.child_indicies[slot] {
+            EMPTY_NODE => 0.0,
+            child => octree[child as usize].coverage(axis)
+        };
+        let bit = AXIS_BITS[axis];
+        let filled = (0..8)
+            .filter(| slot | slot & bit == 0)
+            .map(| slot | 1.0 - (1.0 - child_coverage(slot)) * (1.0 - child_coverage(slot | bit)))
+            .sum::<f32>() / 4.0;
+
+        coverage | ((filled * COVERAGE_MAX as f32).round() as u32) << (axis as u32 * COVERAGE_BITS)
+    })
+}
+
+fn add(histogram: &mut Histogram, palette_idx: u32, part: f64) {
+    match histogram.iter_mut().find(| (idx, _) | *idx == palette_idx) {
+        Some((_, total)) => *total += part,
+        None => histogram.push((palette_idx, part))
+    }
+}
+
+// how many reachable nodes have each node as a child, a child listed twice counts twice
+fn count_parents(octree: &[VoxelData]) -> Vec<u32> {
+    let mut parents = vec![0; octree.len()];
+    let mut stack = vec![0];
+
+    while let Some(index) = stack.pop() {
+        for &child in octree[index as usize].child_indicies.iter().filter(| &&child | child != EMPTY_NODE) {
+            parents[child as usize] += 1;
+
+            // its children are counted the first time it is seen
+            if parents[child as usize] == 1 {
+                stack.push(child);
+            }
+        }
+    }
+
+    parents
+}
+
+/// Sets the palette index of every interior node reachable from the root to the entry covering
+/// most of its volume, and its coverage. Leaves are left alone. Has to run again after leaves are edited.
+/// Bottom up in one walk, a histogram lives only until its parent is done, or its last parent for
+/// nodes shared in a DAG
+pub fn aggregate(octree: &mut [VoxelData]) {
+    let pending_parents = count_parents(octree);
+
+    Aggregator { octree, pending_parents, shared: HashMap::new() }.aggregate(0);
+}
+
+
+
+#[test]
+fn test_aggregate() {
+    use super::{camera::Vec3, dag, gpu_shared_data::FULL_COVERAGE, raycast::trace, voxel_data_generator::generate_tree};
+
+    // a leaf in slot 0, seven voxels of entry 5 one layer deeper in slot 1 and one of entry 6
+    let mut octree = vec![VoxelData::leaf(0), VoxelData::leaf(3), VoxelData::leaf(0)];
+    octree[0].child_indicies[0] = 1;
+    octree[0].child_indicies[1] = 2;
+
+    for slot in 0..8 {
+        octree[2].child_indicies[slot] = octree.len() as u32;
+        octree.push(VoxelData::leaf(if slot == 0 { 6 } else { 5 }));
+    }
+
+    aggregate(&mut octree);
+
+    // the single leaf covers more than all seven small ones
+    assert_eq!(octree[0].pallete_idx, 3);
+    assert_eq!(octree[2].pallete_idx, 5);
+    assert_eq!(octree[3].pallete_idx, 6);
+
+    // both children are on the -Y -Z side of the root, next to each other along x
+    let coverage = [0, 1, 2].map(| axis | octree[0].coverage(axis));
+
+    assert!(coverage.iter().zip([0.25, 0.5, 0.5]).all(| (coverage, expected) | (coverage - expected).abs() < 1e-3));
+    assert_eq!(octree[2].coverage, FULL_COVERAGE);
+
+    // builders aggregate already, equal subtrees still merge
+    let octree = generate_tree(4);
+    let mut aggregated = octree.clone();
+    aggregate(&mut aggregated);
+
+    assert_eq!(aggregated, octree);
+    assert_eq!(dag::compress(&octree).0.len(), dag::compress(&aggregated).0.len());
+
+    // shared nodes come out the same as in the tree they were merged from
+    let (dag, _) = dag::compress(&octree);
+    let mut aggregated = dag.clone();
+    aggregate(&mut aggregated);
+
+    assert_eq!(aggregated, dag);
+
+    // any distance is far enough for a huge threshold, the walk stops below the root. Along y
+    // the node is filled, so no ray passes it
+    assert_eq!(octree[octree[0].child_indicies[0] as usize].coverage(1), 1.0);
+    let res = trace(&octree, Vec3::new(-0.5, -3.0, -0.5), Vec3::y(), 10.0);
+    let hit = res.hit.unwrap();
+
+    assert_eq!((res.depth_reached, hit.size()), (1, 1.0));
+    assert_eq!(hit.node_index, octree[0].child_indicies[0]);
+
+    // a node holding a single voxel covers a quarter of its face, and stops about as many rays
+    let mut octree = vec![VoxelData::leaf(0), VoxelData::leaf(0), VoxelData::leaf(0)];
+    octree[0].child_indicies[0] = 1;
+    octree[1].child_indicies[0] = 2;
+    aggregate(&mut octree);
+
+    // all towards the face of the node, spread over a 32x32 grid
+    let hits = (0..32 * 32)
+        .filter(| idx | {
+            let offset = Vec3::new((idx % 32) as f32, (idx / 32) as f32, 0.0) / 32.0 * 0.4 - Vec3::new(0.2, 0.2, 0.0);
+
+            trace(&octree, Vec3::new(-0.5, -0.5, -3.0), (Vec3::z() + offset).normalize(), 10.0).hit.is_some()
+        })
+        .count();
+
+    assert!((hits as f32 / 1024.0 - 0.25).abs() < 0.05);
+}
@@ -0,0 +1,290 @@
+//! Ray queries against the octree on the CPU, for picking and editing.
+//! Port of the traversal in rendering_shader.comp, so keep it in sync with the shader!
+
+use std::fmt;
+
+use super::{camera::{UVec3, Vec3}, gpu_shared_data::{VoxelData, EMPTY_NODE}};
+
+/// Nodes on this layer are drawn solid even if they have children, the root spans [-1, 1]
+pub const MAX_WALK_DEPTH: usize = 12;
+const MIPE: usize = 4; // max intersections per layer
+
+// same as OFFSETS in the shader: bit 0 is x, bit 1 is z and bit 2 is y
+fn child_offset(idx: usize) -> Vec3 {
+    let coord = child_coord(idx).cast::<f32>();
+
+    coord * 2.0 - Vec3::repeat(1.0)
+}
+
+// position of a child inside its parent, 0 or 1 on every axis
+fn child_coord(idx: usize) -> UVec3 {
+    let idx = idx as u32;
+
+    UVec3::new(idx & 1, (idx >> 2) & 1, (idx >> 1) & 1)
+}
+
+
+/// Voxel face, in the order the shader tests them. Discriminants are `face` in the shader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Face {
+    NegX = 0,
+    NegY = 1,
+    NegZ = 2,
+    PosX = 3,
+    PosY = 4,
+    PosZ = 5
+}
+
+impl Face {
+    pub const ALL: [Self; 6] = [Self::NegX, Self::NegY, Self::NegZ, Self::PosX, Self::PosY, Self::PosZ];
+
+    /// Outward facing unit normal
+    pub fn normal(self) -> Vec3 {
+        let axis = Vec3::ith(self as usize % 3, 1.0);
+
+        if (self as usize) < 3 { -axis } else { axis }
+    }
+}
+
+impl fmt::Display for Face {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NegX => "-X",
+            Self::NegY => "-Y",
+            Self::NegZ => "-Z",
+            Self::PosX => "+X",
+            Self::PosY => "+Y",
+            Self::PosZ => "+Z"
+        })
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// In multiples of the ray direction
+    pub dist: f32,
+    pub face: Face,
+    /// Index of the hit voxel in the octree buffer
+    pub node_index: u32,
+    /// Layer of the hit node, the root is layer 0
+    pub depth: u32,
+    // child slots from the root down, the first `depth` are used
+    slots: [usize; MAX_WALK_DEPTH]
+}
+
+impl RayHit {
+    pub fn normal(&self) -> Vec3 {
+        self.face.normal()
+    }
+
+    /// Child slots from the root down to the hit voxel, one per layer
+    pub fn path(&self) -> &[usize] {
+        &self.slots[..self.depth as usize]
+    }
+
+    /// Position of the hit node in the grid of its layer, from the -X -Y -Z corner
+    pub fn coords(&self) -> UVec3 {
+        self.path().iter().fold(UVec3::zeros(), | coords, &slot | coords * 2 + child_coord(slot))
+    }
+
+    /// Edge of the hit node
+    pub fn size(&self) -> f32 {
+        2.0 / (1u32 << self.depth) as f32
+    }
+
+    /// -X -Y -Z corner of the hit node
+    pub fn min_corner(&self) -> Vec3 {
+        self.coords().cast::<f32>() * self.size() - Vec3::repeat(1.0)
+    }
+}
+
+/// Traversal statistics along with the hit, for the debug views
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceResult {
+    pub hit: Option<RayHit>,
+    /// Deepest layer with an intersection
+    pub depth_reached: u32,
+    pub box_tests: u32
+}
+
+struct LayerHit {
+    dist: f32,
+    face: Face,
+    child_index: usize,
+    octree_index: u32
+}
+
+// closest face the ray enters or leaves through, planes the ray is parallel to are skipped.
+// dist is negative if the origin is inside the box
+fn ray2aab_intersection(origin: Vec3, direction: Vec3, extents: Vec3) -> Option<(f32, Face)> {
+    let mut max_dist = f32::NEG_INFINITY;
+
+    let closest = Face::ALL.into_iter()
+        .filter_map(| face | {
+            let normal = face.normal();
+            let dt_dir = direction.dot(&normal);
+
+            if dt_dir == 0.0 {
+                return None;
+            }
+
+            let dist_from_center = extents[face as usize % 3];
+            let dist = normal.dot(&(normal * dist_from_center - origin)) / dt_dir;
+            let point = origin + direction * dist;
+            let is_inside = point.abs().iter()
+                .zip((extents * 1.01).iter())
+                .all(| (p, e) | p <= e);
+
+            is_inside.then_some((dist, face))
+        })
+        .inspect(| &(dist, _) | max_dist = max_dist.max(dist))
+        .fold(None, | closest: Option<(f32, Face)>, (dist, face) | match closest {
+            Some((min_dist, _)) if min_dist <= dist => closest,
+            _ => Some((dist, face))
+        });
+
+    // box is behind the ray if it is left before the origin
+    closest.filter(| _ | max_dist >= 0.0)
+}
+
+fn intersect_layer(octree: &[VoxelData], origin: Vec3, direction: Vec3, pos: Vec3, extent: f32, octree_index: u32, box_tests: &mut u32) -> Vec<LayerHit> {
+    let origin = origin - pos;
+    let extent = extent / 2.0;
+
+    let mut hits = Vec::with_capacity(MIPE);
+
+    for (idx, &child) in octree[octree_index as usize].child_indicies.iter().enumerate() {
+        if child == EMPTY_NODE {
+            continue;
+        }
+
+        let child_origin = origin - child_offset(idx) * extent;
+        let res = ray2aab_intersection(child_origin, direction, Vec3::repeat(extent));
+        *box_tests += 1;
+
+        if let Some((dist, face)) = res.filter(| _ | hits.len() < MIPE) {
+            hits.push(LayerHit { dist, face, child_index: idx, octree_index: child });
+        }
+    }
+
+    // stable, same as the bubble sort in the shader
+    hits.sort_by(| a, b | a.dist.total_cmp(&b.dist));
+
+    hits
+}
+
+// a node this large this far away is smaller than lod_angle, so its aggregate stands in for its children
+fn is_below_lod(dist: f32, node_size: f32, lod_angle: f32) -> bool {
+    node_size < dist * lod_angle
+}
+
+// lowbias32 by Chris Wellons
+pub fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+
+    x
+}
+
+/// [0, 1), 24 bits is all a float can hold
+pub fn unit_float(x: u32) -> f32 {
+    (x >> 8) as f32 / 16777216.0
+}
+
+// a node standing in for its children stops as many rays as its children would, those entering
+// through a face stop with the coverage along its axis. Which ones is hashed from the ray and the
+// node, so a pixel picks the same each frame and a ray crossing several nodes picks for each anew
+fn stops_at_lod(node: &VoxelData, node_index: u32, face: Face, direction: Vec3) -> bool {
+    let threshold = unit_float(hash(direction.x.to_bits() ^ hash(direction.y.to_bits() ^ hash(direction.z.to_bits() ^ hash(node_index)))));
+
+    node.coverage(face as usize % 3) > threshold
+}
+
+/// Walks the octree exactly like the shader does, down to the first leaf hit. `lod_angle` is the
+/// size of a pixel at unit distance times the LOD threshold in pixels, zero walks down to the leaves.
+/// Nodes smaller than that are hit or passed by as many rays as their coverage says
+pub fn trace(octree: &[VoxelData], origin: Vec3, direction: Vec3, lod_angle: f32) -> TraceResult {
+    let mut res = TraceResult { hit: None, depth_reached: 0, box_tests: 0 };
+
+    // children intersected on every layer of the current path, closest first, and the centre of their parent
+    let root_children = intersect_layer(octree, origin, direction, Vec3::zeros(), 1.0, 0, &mut res.box_tests);
+    let mut stack = vec![(root_children.into_iter(), Vec3::zeros())];
+    let mut slots = [0; MAX_WALK_DEPTH];
+
+    while let Some((children, parent_pos)) = stack.last_mut() {
+        let parent_pos = *parent_pos;
+        let Some(child) = children.next() else {
+            stack.pop();
+            continue;
+        };
+
+        let depth = stack.len();
+        let half_size = 1.0 / (1u32 << depth) as f32;
+        let pos = parent_pos + child_offset(child.child_index) * half_size;
+
+        slots[depth - 1] = child.child_index;
+        res.depth_reached = res.depth_reached.max(depth as u32);
+
+        let node = &octree[child.octree_index as usize];
+        let is_leaf = node.is_leaf();
+        let is_lod = !is_leaf && is_below_lod(child.dist, half_size * 2.0, lod_angle);
+
+        if is_lod && !stops_at_lod(node, child.octree_index, child.face, direction) {
+            continue;
+        }
+
+        if is_leaf || is_lod || depth == MAX_WALK_DEPTH {
+            res.hit = Some(RayHit { dist: child.dist, face: child.face, node_index: child.octree_index, depth: depth as u32, slots });
+
+            return res;
+        }
+
+        let grandchildren = intersect_layer(octree, origin, direction, pos, half_size, child.octree_index, &mut res.box_tests);
+        stack.push((grandchildren.into_iter(), pos));
+    }
+
+    res
+}
+
+/// Closest voxel along the ray, if any
+pub fn raycast(octree: &[VoxelData], origin: Vec3, direction: Vec3) -> Option<RayHit> {
+    trace(octree, origin, direction, 0.0).hit
+}
+
+
+
+#[test]
+fn test_raycast() {
+    use super::voxel_data_generator::generate_tree;
+
+    let octree = generate_tree(4);
+    // centre of a voxel in the grid of the deepest layer
+    let voxel_center = | x: f32, y: f32 | Vec3::new(-0.875 + x * 0.25, -0.875 + y * 0.25, -3.0);
+
+    let hit = raycast(&octree, voxel_center(0.0, 0.0), Vec3::z()).unwrap();
+
+    assert_eq!(hit.coords(), UVec3::new(0, 0, 0));
+    assert_eq!((hit.depth, hit.size()), (3, 0.25));
+    assert_eq!(hit.face, Face::NegZ);
+    assert_eq!(hit.normal(), -Vec3::z());
+    assert!((hit.dist - 2.0).abs() < 1e-5);
+
+    // every node has children 0, 1, 2, 5 and 7, so x = 3 is child 1 on every layer but the root
+    let hit = raycast(&octree, voxel_center(3.0, 0.0), Vec3::z()).unwrap();
+
+    assert_eq!(hit.coords(), UVec3::new(3, 0, 0));
+    assert_eq!(octree[octree[octree[0].child_indicies[0] as usize].child_indicies[1] as usize].child_indicies[1], hit.node_index);
+    assert_eq!(hit.path(), [0, 1, 1]);
+
+    let hit = raycast(&octree, Vec3::new(3.0, -0.875, -0.875), -Vec3::x()).unwrap();
+
+    assert_eq!(hit.face, Face::PosX);
+    assert_eq!(hit.coords().x, 7);
+    assert_eq!(hit.min_corner().x, 0.75);
+
+    assert_eq!(raycast(&octree, voxel_center(0.0, 0.0), -Vec3::z()), None);
+}
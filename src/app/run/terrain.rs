@@ -0,0 +1,215 @@
+//! Terrain octrees from seeded fractal value noise: a height per column, layers of grass, dirt
+//! and stone below it, water up to the sea level and optional tunnels carved by 3D noise.
+//! World up is -Y, so the ground lies on the +Y side of the root
+
+use std::array;
+
+use crate::app::config::TerrainParams;
+
+use super::{builder::{self, Built}, camera::{UVec3, Vec3}, gpu_shared_data::{PaletteEntry, VoxelData}};
+
+pub const GRASS: u32 = 0;
+pub const DIRT: u32 = 1;
+pub const STONE: u32 = 2;
+pub const WATER: u32 = 3;
+
+/// Indexed by the constants above
+pub const PALETTE: [PaletteEntry; 4] = [
+    PaletteEntry::diffuse(Vec3::new(0.25, 0.5, 0.15)),
+    PaletteEntry::diffuse(Vec3::new(0.4, 0.28, 0.17)),
+    PaletteEntry::diffuse(Vec3::new(0.45, 0.45, 0.47)),
+    PaletteEntry::glass(Vec3::new(0.55, 0.75, 0.85), 1.33)
+];
+
+// lowbias32 by Chris Wellons, same as in the shader
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+
+    x
+}
+
+// [0, 1] at every lattice point, smoothly interpolated in between
+fn value_noise(seed: u32, pos: Vec3) -> f32 {
+    let cell = pos.map(f32::floor);
+    let t = (pos - cell).map(| t | t * t * (3.0 - 2.0 * t));
+    let lattice = | offset: UVec3 | {
+        let corner = cell.map(| c | c as i32 as u32) + offset;
+
+        hash(corner.z ^ hash(corner.y ^ hash(corner.x ^ hash(seed)))) as f32 / u32::MAX as f32
+    };
+
+    let lerp = | a: f32, b: f32, t: f32 | a + (b - a) * t;
+    let along_x = | y, z | lerp(lattice(UVec3::new(0, y, z)), lattice(UVec3::new(1, y, z)), t.x);
+
+    lerp(
+        lerp(along_x(0, 0), along_x(1, 0), t.y),
+        lerp(along_x(0, 1), along_x(1, 1), t.y),
+        t.z
+    )
+}
+
+// octaves of value noise at doubling frequency and halving amplitude, normalized to [0, 1]
+fn fractal_noise(seed: u32, pos: Vec3, octaves: u32) -> f32 {
+    let (sum, total_amplitude) = (0..octaves).fold((0.0, 0.0), | (sum, total), octave | {
+        let amplitude = 0.5f32.powi(octave as i32);
+
+        (sum + value_noise(seed.wrapping_add(octave), pos * (1 << octave) as f32) * amplitude, total + amplitude)
+    });
+
+    sum / total_amplitude
+}
+
+struct TerrainBuilder<'a> {
+    params: &'a TerrainParams,
+    /// Voxels per edge
+    width: u32,
+    /// Solid voxels in each column counted from the bottom, indexed by x + z * width
+    heights: Vec<u32>,
+    /// Palette entry of every column in place of the grass, dirt and stone layers, same indexing
+    colors: Option<Vec<u32>>,
+    sea_level: u32
+}
+
+impl TerrainBuilder<'_> {
+    // y grows downwards, so it is flipped to get the height above the bottom
+    fn level(&self, y: u32) -> u32 {
+        self.width - 1 - y
+    }
+
+    fn is_cave(&self, voxel: UVec3) -> bool {
+        if self.params.caves == 0.0 || voxel.y == self.width - 1 {
+            return false;
+        }
+
+        let pos = voxel.cast::<f32>() / self.width as f32 * self.params.frequency * 2.0;
+
+        (fractal_noise(self.params.seed ^ 0x9e3779b9, pos, 2) - 0.5).abs() < self.params.caves
+    }
+
+    fn voxel(&self, voxel: UVec3) -> Option<u32> {
+        let column = (voxel.x + voxel.z * self.width) as usize;
+        let height = self.heights[column];
+        let level = self.level(voxel.y);
+
+        if level >= height {
+            return (level < self.sea_level).then_some(WATER);
+        }
+
+        if self.is_cave(voxel) {
+            return None;
+        }
+
+        if let Some(colors) = &self.colors {
+            return Some(colors[column]);
+        }
+
+        let below_surface = height - 1 - level;
+
+        Some(match below_surface {
+            // nothing grows under water
+            0 if height > self.sea_level => GRASS,
+            depth if depth <= self.params.dirt => DIRT,
+            _ => STONE
+        })
+    }
+
+    fn build(&self, nodes: &mut Vec<VoxelData>, min: UVec3, size: u32) -> Built {
+        if size == 1 {
+            return self.voxel(min).map_or(Built::Empty, Built::Solid);
+        }
+
+        let columns = || (min.z..min.z + size).flat_map(move | z | (min.x..min.x + size).map(move | x | (x + z * self.width) as usize));
+        let (lowest, highest) = columns()
+            .fold((u32::MAX, 0), | (lowest, highest), column | (lowest.min(self.heights[column]), highest.max(self.heights[column])));
+
+        let bottom = self.level(min.y + size - 1);
+        let top = self.level(min.y);
+
+        // above the ground and the sea
+        if bottom >= highest.max(self.sea_level) {
+            return Built::Empty;
+        }
+
+        // below the surface, nothing changes down there without caves
+        if self.params.caves == 0.0 {
+            match &self.colors {
+                Some(colors) if top < lowest => {
+                    let first = colors[columns().next().unwrap()];
+
+                    if columns().all(| column | colors[column] == first) {
+                        return Built::Solid(first);
+                    }
+                },
+                None if top + self.params.dirt + 1 < lowest => return Built::Solid(STONE),
+                _ => ()
+            }
+        }
+
+        let half = size / 2;
+        let children = array::from_fn(| slot | self.build(nodes, builder::child_min(min, half, slot), half));
+
+        builder::combine(nodes, children)
+    }
+}
+
+/// Builds the terrain with uniform regions merged into single leaves, interior nodes aggregated
+pub fn generate_terrain(params: &TerrainParams) -> Vec<VoxelData> {
+    let width = 1 << params.depth;
+    let heights = (0..width * width)
+        .map(| column | {
+            let pos = Vec3::new((column % width) as f32, (column / width) as f32, 0.0) / width as f32 * params.frequency;
+            let height = fractal_noise(params.seed, pos, params.octaves) * width as f32;
+
+            // at least one voxel of ground everywhere
+            (height.round() as u32).clamp(1, width)
+        })
+        .collect();
+
+    generate_from_heights(params, heights, None)
+}
+
+/// Same as `generate_terrain` with the noise replaced by `heights`, solid voxels per column indexed by
+/// x + z * 2^depth. `colors` paints each column with a single palette entry, indexed the same way
+pub fn generate_from_heights(params: &TerrainParams, heights: Vec<u32>, colors: Option<Vec<u32>>) -> Vec<VoxelData> {
+    let width = 1 << params.depth;
+    let builder = TerrainBuilder { params, width, heights, colors, sea_level: (params.sea_level * width as f32).round() as u32 };
+
+    builder::build(| nodes | builder.build(nodes, UVec3::zeros(), width))
+}
+
+
+
+#[test]
+fn test_terrain() {
+    use super::{inspect::inspect, raycast::raycast};
+
+    let params = TerrainParams { depth: 5, ..Default::default() };
+    let terrain = generate_terrain(&params);
+    let stats = inspect(&terrain).unwrap();
+
+    // same seed, same terrain
+    assert_eq!(generate_terrain(&params), terrain);
+    assert_ne!(generate_terrain(&TerrainParams { seed: 2, ..params }), terrain);
+
+    // merged regions make the tree shallower in places, but never deeper than asked for
+    assert_eq!(stats.max_depth(), params.depth as usize);
+    assert!(stats.filled_fraction > params.sea_level as f64 * 0.9 && stats.filled_fraction < 1.0);
+    assert!(stats.palette_usage.iter().all(| &count | count > 0));
+
+    // straight down onto ground or water from anywhere above the terrain
+    for idx in 0..16 {
+        let origin = Vec3::new(-0.9 + (idx % 4) as f32 * 0.6, -3.0, -0.9 + (idx / 4) as f32 * 0.6);
+        let hit = raycast(&terrain, origin, Vec3::y()).unwrap();
+
+        assert!([GRASS, DIRT, WATER].contains(&terrain[hit.node_index as usize].pallete_idx));
+    }
+
+    // tunnels only take voxels away
+    let caves = inspect(&generate_terrain(&TerrainParams { caves: 0.05, ..params })).unwrap();
+
+    assert!(caves.filled_fraction < stats.filled_fraction);
+}
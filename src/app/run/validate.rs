@@ -0,0 +1,117 @@
+//! Structural checks for octrees coming from anywhere but the built in generator.
+//! The shader trusts child indices blindly, a bad one reads garbage or never terminates
+
+use std::fmt;
+
+use super::gpu_shared_data::{VoxelData, EMPTY_NODE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// There is no root node
+    Empty,
+    /// Child index past the end of the octree
+    OutOfRange { node: u32, slot: usize, child: u32 },
+    /// Child is an ancestor of its parent
+    Cycle { node: u32, slot: usize, child: u32 },
+    /// Node is reachable at two depths, so it would be drawn at two sizes
+    InconsistentDepth { node: u32, first_depth: u32, depth: u32 }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("octree has no nodes"),
+            Self::OutOfRange { node, slot, child } => write!(f, "child {child} in slot {slot} of node {node} is out of range"),
+            Self::Cycle { node, slot, child } => write!(f, "child {child} in slot {slot} of node {node} is one of its ancestors"),
+            Self::InconsistentDepth { node, first_depth, depth } => write!(f, "node {node} is reachable at depth {first_depth} and {depth}")
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+struct Validator<'a> {
+    octree: &'a [VoxelData],
+    // depth each node was first reached at
+    depths: Vec<Option<u32>>,
+    on_path: Vec<bool>,
+    errors: Vec<ValidationError>
+}
+
+impl Validator<'_> {
+    fn visit(&mut self, index: u32, depth: u32) {
+        if let Some(first_depth) = self.depths[index as usize] {
+            if first_depth != depth {
+                self.errors.push(ValidationError::InconsistentDepth { node: index, first_depth, depth });
+            }
+
+            // subtree was checked already
+            return;
+        }
+
+        self.depths[index as usize] = Some(depth);
+        self.on_path[index as usize] = true;
+
+        for (slot, child) in self.octree[index as usize].child_indicies.into_iter().enumerate().filter(| (_, child) | *child != EMPTY_NODE) {
+            if child as usize >= self.octree.len() {
+                self.errors.push(ValidationError::OutOfRange { node: index, slot, child });
+            } else if self.on_path[child as usize] {
+                self.errors.push(ValidationError::Cycle { node: index, slot, child });
+            } else {
+                self.visit(child, depth + 1);
+            }
+        }
+
+        self.on_path[index as usize] = false;
+    }
+}
+
+/// Walks everything reachable from the root at index 0. Shared subtrees are fine as long as
+/// they are always reached at the same depth, unreachable nodes are ignored
+pub fn validate(octree: &[VoxelData]) -> Result<(), Vec<ValidationError>> {
+    if octree.is_empty() {
+        return Err(vec![ValidationError::Empty]);
+    }
+
+    let mut validator = Validator {
+        octree,
+        depths: vec![None; octree.len()],
+        on_path: vec![false; octree.len()],
+        errors: Vec::new()
+    };
+
+    validator.visit(0, 0);
+
+    if validator.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(validator.errors)
+    }
+}
+
+
+
+#[test]
+fn test_validation() {
+    use super::{dag, voxel_data_generator::generate_tree};
+
+    let octree = generate_tree(4);
+
+    assert_eq!(validate(&octree), Ok(()));
+    assert_eq!(validate(&dag::compress(&octree).0), Ok(()));
+    assert_eq!(validate(&[]), Err(vec![ValidationError::Empty]));
+
+    // node 1 points past the end and back at the root, node 2 hangs below both of them
+    let mut nodes = vec![VoxelData::leaf(0), VoxelData::leaf(1), VoxelData::leaf(2)];
+    nodes[0].child_indicies[3] = 1;
+    nodes[1].child_indicies[0] = 2;
+    nodes[1].child_indicies[1] = 3;
+    nodes[1].child_indicies[2] = 0;
+    nodes[0].child_indicies[4] = 2;
+
+    assert_eq!(validate(&nodes), Err(vec![
+        ValidationError::OutOfRange { node: 1, slot: 1, child: 3 },
+        ValidationError::Cycle { node: 1, slot: 2, child: 0 },
+        ValidationError::InconsistentDepth { node: 2, first_depth: 2, depth: 1 }
+    ]));
+}
@@ -1,35 +1,48 @@
-use super::gpu_shared_data::VoxelData;
+use super::{camera::Vec3, gpu_shared_data::{PaletteEntry, VoxelData, EMPTY_NODE}, lod};
+
+pub const PALETTE: [PaletteEntry; 8] = [
+    PaletteEntry::diffuse(Vec3::new(0.8, 0.8, 0.8)),
+    PaletteEntry::diffuse(Vec3::new(0.75, 0.25, 0.2)),
+    PaletteEntry::diffuse(Vec3::new(0.25, 0.6, 0.25)),
+    PaletteEntry::glass(Vec3::new(0.85, 0.95, 1.0), 1.5),
+    PaletteEntry::diffuse(Vec3::new(0.2, 0.35, 0.75)),
+    PaletteEntry::metal(Vec3::new(0.95, 0.8, 0.45), 0.15),
+    PaletteEntry::emissive(Vec3::new(0.9, 0.75, 0.5), 6.0),
+    PaletteEntry::metal(Vec3::new(0.9, 0.9, 0.9), 0.0)
+];
 
 pub fn generate_tree(layer_count: u8) -> Vec<VoxelData> {
     let mut dst = Vec::new();
 
     generate_tree_layer_rec(&mut dst, 0, layer_count);
+    lod::aggregate(&mut dst);
 
     dst
 }
 
-fn generate_tree_layer_rec(dst: &mut Vec<VoxelData>, layer_idx: u8, max_layer: u8) -> isize {
+fn generate_tree_layer_rec(dst: &mut Vec<VoxelData>, layer_idx: u8, max_layer: u8) -> u32 {
     if layer_idx == max_layer {
-        return -1;
+        return EMPTY_NODE;
     }
 
-    dst.push(VoxelData { child_indicies: [0, 0, 0, 0, 0, 0, 0, 0], pallete_idx: 0 });
+    dst.push(VoxelData::leaf(0));
 
     let data_index = dst.len() - 1;
+    dst[data_index].pallete_idx = (data_index % PALETTE.len()) as u32;
 
     
-    dst[data_index].child_indicies[0] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer).max(0) as u32;
-    dst[data_index].child_indicies[1] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer).max(0) as u32;
-    dst[data_index].child_indicies[2] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer).max(0) as u32;
-    //dst[data_index].child_indicies[3] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer).max(0) as u32;
+    dst[data_index].child_indicies[0] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer);
+    dst[data_index].child_indicies[1] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer);
+    dst[data_index].child_indicies[2] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer);
+    //dst[data_index].child_indicies[3] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer);
 
-    //dst[data_index].child_indicies[4] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer).max(0) as u32;
-    dst[data_index].child_indicies[5] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer).max(0) as u32;
-    //dst[data_index].child_indicies[6] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer).max(0) as u32;
-    dst[data_index].child_indicies[7] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer).max(0) as u32;
+    //dst[data_index].child_indicies[4] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer);
+    dst[data_index].child_indicies[5] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer);
+    //dst[data_index].child_indicies[6] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer);
+    dst[data_index].child_indicies[7] = generate_tree_layer_rec(dst, layer_idx + 1, max_layer);
 
 
-    data_index as isize
+    data_index as u32
 }
 
 
@@ -37,5 +50,8 @@ fn generate_tree_layer_rec(dst: &mut Vec<VoxelData>, layer_idx: u8, max_layer: u
 fn test_generation() {
     let tree = generate_tree(3);
 
-    println!("{tree:?}");
+    // five children per node, depth first
+    assert_eq!(tree.len(), 1 + 5 + 25);
+    assert_eq!(super::validate::validate(&tree), Ok(()));
+    assert!(tree[2].is_leaf() && !tree[1].is_leaf());
 }
\ No newline at end of file
@@ -0,0 +1,216 @@
+//! Loading of SPIR-V binaries.
+//! Shader blobs are embedded with `include_bytes!`, so nothing guarantees they are
+//! word aligned, complete or even SPIR-V at all. Everything is checked here before
+//! the driver gets to see the words.
+
+use std::fmt;
+
+pub mod reflect;
+
+pub const MAGIC: u32 = 0x0723_0203;
+pub const HEADER_LEN: usize = 5;
+
+/// Newest SPIR-V version we are willing to hand to the driver
+pub const MAX_VERSION: (u8, u8) = (1, 6);
+
+mod op {
+    pub const NAME: u32 = 5;
+    pub const TYPE_BOOL: u32 = 20;
+    pub const TYPE_INT: u32 = 21;
+    pub const TYPE_FLOAT: u32 = 22;
+    pub const TYPE_VECTOR: u32 = 23;
+    pub const TYPE_MATRIX: u32 = 24;
+    pub const TYPE_IMAGE: u32 = 25;
+    pub const TYPE_SAMPLER: u32 = 26;
+    pub const TYPE_SAMPLED_IMAGE: u32 = 27;
+    pub const TYPE_ARRAY: u32 = 28;
+    pub const TYPE_RUNTIME_ARRAY: u32 = 29;
+    pub const TYPE_STRUCT: u32 = 30;
+    pub const TYPE_POINTER: u32 = 32;
+    pub const CONSTANT: u32 = 43;
+    pub const SPEC_CONSTANT: u32 = 50;
+    pub const VARIABLE: u32 = 59;
+    pub const DECORATE: u32 = 71;
+    pub const MEMBER_DECORATE: u32 = 72;
+}
+
+mod decoration {
+    pub const SPEC_ID: u32 = 1;
+    pub const BLOCK: u32 = 2;
+    pub const BUFFER_BLOCK: u32 = 3;
+    pub const ARRAY_STRIDE: u32 = 6;
+    pub const MATRIX_STRIDE: u32 = 7;
+    pub const BINDING: u32 = 33;
+    pub const DESCRIPTOR_SET: u32 = 34;
+    pub const OFFSET: u32 = 35;
+}
+
+mod storage_class {
+    pub const UNIFORM_CONSTANT: u32 = 0;
+    pub const UNIFORM: u32 = 2;
+    pub const STORAGE_BUFFER: u32 = 12;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpirvError {
+    /// Byte length is not a multiple of four
+    UnalignedLength(usize),
+    /// Less than five words, header does not fit
+    TruncatedHeader(usize),
+    BadMagic(u32),
+    UnsupportedVersion { major: u8, minor: u8 },
+    /// Instruction at `offset` (in words) has zero length or runs past the end of the module
+    TruncatedInstruction { offset: usize, word_count: usize },
+    /// No 32 bit scalar specialization constant with this SpecId
+    UnknownSpecConstant(u32)
+}
+
+impl fmt::Display for SpirvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnalignedLength(len) => write!(f, "length of {len} bytes is not a multiple of 4"),
+            Self::TruncatedHeader(words) => write!(f, "module is {words} words long, header alone needs {HEADER_LEN}"),
+            Self::BadMagic(magic) => write!(f, "bad magic number {magic:#010x}, expected {MAGIC:#010x}"),
+            Self::UnsupportedVersion { major, minor } => write!(
+                f,
+                "unsupported version {major}.{minor}, supported are 1.0 - {}.{}",
+                MAX_VERSION.0, MAX_VERSION.1
+            ),
+            Self::TruncatedInstruction { offset, word_count } => write!(
+                f,
+                "instruction at word {offset} claims {word_count} words, module is truncated or corrupted"
+            ),
+            Self::UnknownSpecConstant(spec_id) => write!(f, "no scalar specialization constant with SpecId {spec_id}")
+        }
+    }
+}
+
+impl std::error::Error for SpirvError {}
+
+
+/// Validates a SPIR-V binary and converts it to host-endian words.
+///
+/// Both little and big endian modules are accepted, byte order is detected from the magic number.
+pub fn load(bytes: &[u8]) -> Result<Vec<u32>, SpirvError> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(SpirvError::UnalignedLength(bytes.len()));
+    }
+    if bytes.len() < HEADER_LEN * 4 {
+        return Err(SpirvError::TruncatedHeader(bytes.len() / 4));
+    }
+
+    let first_word = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    let decode: fn([u8; 4]) -> u32 = if u32::from_le_bytes(first_word) == MAGIC {
+        u32::from_le_bytes
+    } else if u32::from_be_bytes(first_word) == MAGIC {
+        u32::from_be_bytes
+    } else {
+        return Err(SpirvError::BadMagic(u32::from_le_bytes(first_word)));
+    };
+
+    let words: Vec<u32> = bytes.chunks_exact(4)
+        .map(| w | decode([w[0], w[1], w[2], w[3]]))
+        .collect();
+
+    // version is encoded as 0x00MMmm00
+    let version = words[1];
+    let major = (version >> 16) as u8;
+    let minor = (version >> 8) as u8;
+
+    if version & 0xFF0000FF != 0 || major != 1 || minor > MAX_VERSION.1 {
+        return Err(SpirvError::UnsupportedVersion { major, minor });
+    }
+
+    let mut offset = HEADER_LEN;
+
+    while offset < words.len() {
+        let word_count = (words[offset] >> 16) as usize;
+
+        if word_count == 0 || offset + word_count > words.len() {
+            return Err(SpirvError::TruncatedInstruction { offset, word_count });
+        }
+
+        offset += word_count;
+    }
+
+    Ok(words)
+}
+
+/// Iterates over (word offset, opcode, operands) of every instruction.
+/// `words` must come from [`load`], framing is not checked again
+fn instructions(words: &[u32]) -> impl Iterator<Item = (usize, u32, &[u32])> {
+    let mut offset = HEADER_LEN;
+
+    core::iter::from_fn(move || {
+        let word_count = (*words.get(offset)? >> 16) as usize;
+        let instruction = (offset, words[offset] & 0xFFFF, &words[offset + 1..offset + word_count]);
+
+        offset += word_count;
+
+        Some(instruction)
+    })
+}
+
+/// Overrides the default value of a 32 bit scalar specialization constant.
+///
+/// Pipeline creation takes no specialization info in our Vulkan wrapper, so defaults
+/// are patched right in the binary. For the driver it is the same thing
+pub fn specialize(words: &mut [u32], spec_id: u32, value: u32) -> Result<(), SpirvError> {
+    let target = instructions(words)
+        .find_map(| (_, opcode, operands) | match (opcode, operands) {
+            (op::DECORATE, &[target, decoration::SPEC_ID, id]) if id == spec_id => Some(target),
+            _ => None
+        })
+        .ok_or(SpirvError::UnknownSpecConstant(spec_id))?;
+
+    // OpSpecConstant <result type> <result id> <value>
+    let value_offset = instructions(words)
+        .find_map(| (offset, opcode, operands) | match (opcode, operands) {
+            (op::SPEC_CONSTANT, &[_, id, _]) if id == target => Some(offset + 3),
+            _ => None
+        })
+        .ok_or(SpirvError::UnknownSpecConstant(spec_id))?;
+
+    words[value_offset] = value;
+
+    Ok(())
+}
+
+
+
+#[test]
+fn test_load() {
+    let shader = include_bytes!("../shader/rendering_shader.spv");
+    let words = load(shader).unwrap();
+
+    assert_eq!(words.len() * 4, shader.len());
+    assert_eq!(words[0], MAGIC);
+
+    // byte swapped module should decode to the same words
+    let swapped: Vec<u8> = shader.chunks_exact(4)
+        .flat_map(| w | [w[3], w[2], w[1], w[0]])
+        .collect();
+    assert_eq!(load(&swapped).unwrap(), words);
+
+    assert_eq!(load(&shader[..shader.len() - 2]), Err(SpirvError::UnalignedLength(shader.len() - 2)));
+    assert_eq!(load(&shader[..8]), Err(SpirvError::TruncatedHeader(2)));
+    // header followed by the first word of a two word OpCapability
+    assert_eq!(load(&shader[..24]), Err(SpirvError::TruncatedInstruction { offset: 5, word_count: 2 }));
+    assert!(matches!(load(&[0u8; 20]), Err(SpirvError::BadMagic(0))));
+}
+
+#[test]
+fn test_specialize() {
+    let mut words = load(include_bytes!("../shader/rendering_shader.spv")).unwrap();
+    let original = words.clone();
+
+    specialize(&mut words, 0, 16).unwrap();
+
+    let changed: Vec<_> = (0..words.len())
+        .filter(| &idx | words[idx] != original[idx])
+        .collect();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(words[changed[0]], 16);
+
+    assert_eq!(specialize(&mut words, 1234, 1), Err(SpirvError::UnknownSpecConstant(1234)));
+}
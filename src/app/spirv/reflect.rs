@@ -0,0 +1,400 @@
+//! Minimal SPIR-V reflection.
+//! Pulls descriptor bindings out of a module so the hand written descriptor set layouts
+//! can be checked against what the shader actually declares.
+
+use std::{collections::HashMap, fmt};
+
+use super::{decoration, instructions, op, storage_class};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorKind {
+    Sampler,
+    CombinedImageSampler,
+    SampledImage,
+    StorageImage,
+    UniformBuffer,
+    StorageBuffer
+}
+
+/// Memory footprint of a buffer block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLayout {
+    /// Size of everything except the trailing runtime array
+    pub size: u32,
+    /// Element stride of the trailing runtime array, if there is one
+    pub runtime_array_stride: Option<u32>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorBindingInfo {
+    pub set: u32,
+    pub binding: u32,
+    pub name: String,
+
+    pub kind: DescriptorKind,
+    pub count: u32,
+    pub block: Option<BlockLayout>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReflectError {
+    /// Instruction references a type or constant which was never declared
+    UnknownId(u32),
+    UnsupportedType(u32)
+}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownId(id) => write!(f, "reference to undeclared id %{id}"),
+            Self::UnsupportedType(id) => write!(f, "type %{id} can not be reflected")
+        }
+    }
+}
+
+impl std::error::Error for ReflectError {}
+
+
+/// What the Rust side expects at a binding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpectedBinding {
+    pub kind: DescriptorKind,
+    pub block: Option<ExpectedBlock>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedBlock {
+    /// Plain struct, buffer must be at least as large as the shader block
+    Sized(usize),
+    /// Block is a single runtime array of elements with the given size
+    Array { stride: usize }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindingMismatch {
+    MissingInShader { set: u32, binding: u32 },
+    MissingInLayout { set: u32, binding: u32, name: String },
+    Kind { set: u32, binding: u32, name: String, shader: DescriptorKind, layout: DescriptorKind },
+    Count { set: u32, binding: u32, name: String, shader: u32 },
+    BlockSize { set: u32, binding: u32, name: String, shader: u32, layout: usize },
+    ArrayStride { set: u32, binding: u32, name: String, shader: Option<u32>, layout: usize }
+}
+
+impl fmt::Display for BindingMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingInShader { set, binding } =>
+                write!(f, "set {set} binding {binding} is in the layout, but not declared by the shader"),
+            Self::MissingInLayout { set, binding, name } =>
+                write!(f, "set {set} binding {binding} ({name}) is declared by the shader, but missing in the layout"),
+            Self::Kind { set, binding, name, shader, layout } =>
+                write!(f, "set {set} binding {binding} ({name}) is {shader:?} in the shader, but {layout:?} in the layout"),
+            Self::Count { set, binding, name, shader } =>
+                write!(f, "set {set} binding {binding} ({name}) is an array of {shader} descriptors, layout has one"),
+            Self::BlockSize { set, binding, name, shader, layout } =>
+                write!(f, "set {set} binding {binding} ({name}) block is {shader} bytes in the shader, but only {layout} bytes on the Rust side"),
+            Self::ArrayStride { set, binding, name, shader: Some(shader), layout } =>
+                write!(f, "set {set} binding {binding} ({name}) array stride is {shader} bytes in the shader, but {layout} bytes on the Rust side"),
+            Self::ArrayStride { set, binding, name, shader: None, layout } =>
+                write!(f, "set {set} binding {binding} ({name}) has no runtime array, but Rust side expects one with {layout} byte elements")
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+enum Type {
+    Scalar { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray,
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 }
+}
+
+#[derive(Default)]
+struct Module {
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    // (id, storage class, pointer type)
+    variables: Vec<(u32, u32, u32)>,
+
+    decorations: HashMap<(u32, u32), u32>,
+    member_decorations: HashMap<(u32, u32, u32), u32>
+}
+
+fn decode_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words.iter()
+        .flat_map(| w | w.to_le_bytes())
+        .take_while(| &b | b != 0)
+        .collect();
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl Module {
+    fn parse(words: &[u32]) -> Self {
+        let mut module = Self::default();
+
+        for (_, opcode, args) in instructions(words) {
+            match (opcode, args) {
+                (op::NAME, [target, name @ ..]) => { module.names.insert(*target, decode_string(name)); },
+
+                (op::TYPE_BOOL, [id]) => { module.types.insert(*id, Type::Scalar { width: 32 }); },
+                (op::TYPE_INT | op::TYPE_FLOAT, [id, width, ..]) => { module.types.insert(*id, Type::Scalar { width: *width }); },
+                (op::TYPE_VECTOR, [id, component, count]) => { module.types.insert(*id, Type::Vector { component: *component, count: *count }); },
+                (op::TYPE_MATRIX, [id, column, count]) => { module.types.insert(*id, Type::Matrix { column: *column, count: *count }); },
+                (op::TYPE_IMAGE, [id, _, _, _, _, _, sampled, ..]) => { module.types.insert(*id, Type::Image { sampled: *sampled }); },
+                (op::TYPE_SAMPLER, [id]) => { module.types.insert(*id, Type::Sampler); },
+                (op::TYPE_SAMPLED_IMAGE, [id, _]) => { module.types.insert(*id, Type::SampledImage); },
+                (op::TYPE_ARRAY, [id, element, length]) => { module.types.insert(*id, Type::Array { element: *element, length: *length }); },
+                (op::TYPE_RUNTIME_ARRAY, [id, _]) => { module.types.insert(*id, Type::RuntimeArray); },
+                (op::TYPE_STRUCT, [id, members @ ..]) => { module.types.insert(*id, Type::Struct { members: members.to_vec() }); },
+                (op::TYPE_POINTER, [id, _, pointee]) => { module.types.insert(*id, Type::Pointer { pointee: *pointee }); },
+
+                // 64 bit constants are never used as array lengths
+                (op::CONSTANT, [_, id, value, ..]) => { module.constants.insert(*id, *value); },
+                (op::VARIABLE, [ty, id, storage_class, ..]) => module.variables.push((*id, *storage_class, *ty)),
+
+                (op::DECORATE, [target, decoration, value @ ..]) => {
+                    module.decorations.insert((*target, *decoration), value.first().copied().unwrap_or(0));
+                },
+                (op::MEMBER_DECORATE, [target, member, decoration, value @ ..]) => {
+                    module.member_decorations.insert((*target, *member, *decoration), value.first().copied().unwrap_or(0));
+                },
+
+                _ => {}
+            }
+        }
+
+        module
+    }
+
+    fn ty(&self, id: u32) -> Result<&Type, ReflectError> {
+        self.types.get(&id).ok_or(ReflectError::UnknownId(id))
+    }
+
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    fn array_length(&self, length_id: u32) -> Result<u32, ReflectError> {
+        self.constants.get(&length_id).copied().ok_or(ReflectError::UnknownId(length_id))
+    }
+
+    /// Size of a type inside of a block. `matrix_stride` comes from the enclosing struct member
+    fn size_of(&self, id: u32, matrix_stride: Option<u32>) -> Result<u32, ReflectError> {
+        Ok(match self.ty(id)? {
+            Type::Scalar { width } => width / 8,
+            Type::Vector { component, count } => self.size_of(*component, None)? * count,
+            Type::Matrix { column, count } => match matrix_stride {
+                Some(stride) => stride * count,
+                None => self.size_of(*column, None)? * count
+            },
+            Type::Array { element, length } => {
+                let length = self.array_length(*length)?;
+
+                match self.decoration(id, decoration::ARRAY_STRIDE) {
+                    Some(stride) => stride * length,
+                    None => self.size_of(*element, matrix_stride)? * length
+                }
+            },
+            Type::RuntimeArray => 0,
+            Type::Struct { members } => {
+                let mut size = 0;
+
+                for (idx, &member) in members.iter().enumerate() {
+                    let offset = self.member_decorations.get(&(id, idx as u32, decoration::OFFSET))
+                        .copied()
+                        .unwrap_or(size);
+                    let stride = self.member_decorations.get(&(id, idx as u32, decoration::MATRIX_STRIDE)).copied();
+
+                    size = size.max(offset + self.size_of(member, stride)?);
+                }
+
+                size
+            },
+
+            _ => return Err(ReflectError::UnsupportedType(id))
+        })
+    }
+
+    fn block_layout(&self, id: u32) -> Result<BlockLayout, ReflectError> {
+        let runtime_array_stride = match self.ty(id)? {
+            Type::Struct { members } => match members.last() {
+                Some(&last) => match self.ty(last)? {
+                    Type::RuntimeArray => self.decoration(last, decoration::ARRAY_STRIDE),
+                    _ => None
+                },
+                None => None
+            },
+            _ => return Err(ReflectError::UnsupportedType(id))
+        };
+
+        Ok(BlockLayout { size: self.size_of(id, None)?, runtime_array_stride })
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderReflection {
+    pub bindings: Vec<DescriptorBindingInfo>
+}
+
+impl ShaderReflection {
+    /// `words` must come from [`super::load`]
+    pub fn reflect(words: &[u32]) -> Result<Self, ReflectError> {
+        let module = Module::parse(words);
+        let mut bindings = Vec::new();
+
+        for &(id, storage_class, ptr_ty) in &module.variables {
+            let (Some(set), Some(binding)) = (
+                module.decoration(id, decoration::DESCRIPTOR_SET),
+                module.decoration(id, decoration::BINDING)
+            ) else {
+                continue;
+            };
+
+            let Type::Pointer { pointee, .. } = *module.ty(ptr_ty)? else {
+                return Err(ReflectError::UnsupportedType(ptr_ty));
+            };
+
+            // arrays of descriptors
+            let (ty, count) = match *module.ty(pointee)? {
+                Type::Array { element, length } => (element, module.array_length(length)?),
+                _ => (pointee, 1)
+            };
+
+            let kind = match (storage_class, module.ty(ty)?) {
+                (storage_class::UNIFORM, Type::Struct { .. }) if module.decoration(ty, decoration::BUFFER_BLOCK).is_some() => DescriptorKind::StorageBuffer,
+                (storage_class::UNIFORM, Type::Struct { .. }) if module.decoration(ty, decoration::BLOCK).is_some() => DescriptorKind::UniformBuffer,
+                (storage_class::STORAGE_BUFFER, Type::Struct { .. }) => DescriptorKind::StorageBuffer,
+
+                (storage_class::UNIFORM_CONSTANT, Type::Image { sampled: 2 }) => DescriptorKind::StorageImage,
+                (storage_class::UNIFORM_CONSTANT, Type::Image { .. }) => DescriptorKind::SampledImage,
+                (storage_class::UNIFORM_CONSTANT, Type::SampledImage) => DescriptorKind::CombinedImageSampler,
+                (storage_class::UNIFORM_CONSTANT, Type::Sampler) => DescriptorKind::Sampler,
+
+                _ => return Err(ReflectError::UnsupportedType(ty))
+            };
+
+            let block = match kind {
+                DescriptorKind::UniformBuffer | DescriptorKind::StorageBuffer => Some(module.block_layout(ty)?),
+                _ => None
+            };
+
+            // anonymous blocks have no variable name, use block name then
+            let name = module.names.get(&id)
+                .filter(| name | !name.is_empty())
+                .or_else(|| module.names.get(&ty))
+                .cloned()
+                .unwrap_or_default();
+
+            bindings.push(DescriptorBindingInfo { set, binding, name, kind, count, block });
+        }
+
+        bindings.sort_by_key(| b | (b.set, b.binding));
+
+        Ok(Self { bindings })
+    }
+
+    /// Member offsets of the struct type named `name`, so Rust side layouts can be tested against GLSL.
+    /// Copies of a block struct in function scope get a second, undecorated type, those are skipped
+    #[cfg(test)]
+    pub fn struct_member_offsets(words: &[u32], name: &str) -> Option<Vec<u32>> {
+        let module = Module::parse(words);
+
+        module.types.iter()
+            .filter_map(| (&id, ty) | match ty {
+                Type::Struct { members } if module.names.get(&id).is_some_and(| n | n == name) => Some((id, members.len() as u32)),
+                _ => None
+            })
+            .find_map(| (id, member_count) | (0..member_count)
+                .map(| member | module.member_decorations.get(&(id, member, decoration::OFFSET)).copied())
+                .collect()
+            )
+    }
+
+    /// Compares descriptor set `set` against a layout, where binding number is the index in `layout`.
+    /// Returns every mismatch found, not only the first one
+    pub fn check_set(&self, set: u32, layout: &[ExpectedBinding]) -> Result<(), Vec<BindingMismatch>> {
+        let mut mismatches = Vec::new();
+
+        for (binding, expected) in layout.iter().enumerate() {
+            let binding = binding as u32;
+            let Some(info) = self.bindings.iter().find(| b | b.set == set && b.binding == binding) else {
+                mismatches.push(BindingMismatch::MissingInShader { set, binding });
+                continue;
+            };
+            let name = info.name.clone();
+
+            if info.kind != expected.kind {
+                mismatches.push(BindingMismatch::Kind { set, binding, name, shader: info.kind, layout: expected.kind });
+                continue;
+            }
+            if info.count != 1 {
+                mismatches.push(BindingMismatch::Count { set, binding, name: name.clone(), shader: info.count });
+            }
+
+            match (info.block, expected.block) {
+                (Some(block), Some(ExpectedBlock::Sized(size))) if (block.size as usize) > size =>
+                    mismatches.push(BindingMismatch::BlockSize { set, binding, name, shader: block.size, layout: size }),
+                (Some(block), Some(ExpectedBlock::Array { stride })) if block.runtime_array_stride.map(| s | s as usize) != Some(stride) =>
+                    mismatches.push(BindingMismatch::ArrayStride { set, binding, name, shader: block.runtime_array_stride, layout: stride }),
+
+                _ => {}
+            }
+        }
+
+        for info in self.bindings.iter().filter(| b | b.set == set && b.binding as usize >= layout.len()) {
+            mismatches.push(BindingMismatch::MissingInLayout { set, binding: info.binding, name: info.name.clone() });
+        }
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(mismatches)
+        }
+    }
+}
+
+
+
+#[test]
+fn test_reflect_rendering_shader() {
+    let words = super::load(include_bytes!("../../shader/rendering_shader.spv")).unwrap();
+    let reflection = ShaderReflection::reflect(&words).unwrap();
+
+    let kinds: Vec<_> = reflection.bindings.iter()
+        .map(| b | (b.set, b.binding, b.kind))
+        .collect();
+    // bindings after these are covered by the Rust side layout test
+    assert_eq!(
+        kinds[..3],
+        [(0, 0, DescriptorKind::UniformBuffer), (0, 1, DescriptorKind::StorageBuffer), (0, 2, DescriptorKind::StorageImage)]
+    );
+
+    // starts with vec3 + mat3 with 16 byte columns, exact size is checked against the Rust side
+    let uniform_block = reflection.bindings[0].block.unwrap();
+    assert!(uniform_block.size >= 64 && uniform_block.runtime_array_stride.is_none());
+    // uint[8] + two uints
+    assert_eq!(reflection.bindings[1].block, Some(BlockLayout { size: 0, runtime_array_stride: Some(40) }));
+
+    let mismatches = reflection.check_set(
+        0,
+        &[
+            ExpectedBinding { kind: DescriptorKind::StorageBuffer, block: None },
+            ExpectedBinding { kind: DescriptorKind::StorageBuffer, block: Some(ExpectedBlock::Array { stride: 32 }) }
+        ]
+    ).unwrap_err();
+
+    assert_eq!(mismatches.len(), kinds.len());
+    assert!(matches!(mismatches[0], BindingMismatch::Kind { binding: 0, .. }));
+    assert!(matches!(mismatches[1], BindingMismatch::ArrayStride { binding: 1, shader: Some(40), layout: 32, .. }));
+    assert!(mismatches[2..].iter().all(| m | matches!(m, BindingMismatch::MissingInLayout { .. })));
+}
@@ -1,7 +1,60 @@
 mod app;
 
+use app::{config::{Command, Config, ConfigError}, CpuReferenceError};
+
 fn main() {
-    let application = app::Application::init();
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(ConfigError::HelpRequested) => {
+            print!("{}", app::config::USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("{e}\n\n{}", app::config::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let world = match app::load_world(&config) {
+        Ok(world) => world,
+        Err(e) => {
+            eprintln!("failed to import {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if config.command == Command::Inspect {
+        if let Err(errors) = app::inspect_octree(&world) {
+            for e in errors {
+                eprintln!("invalid octree: {e}");
+            }
+
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    if let Some(path) = &config.cpu_reference {
+        match app::render_cpu_reference(&config, &world, path) {
+            Ok(()) => {},
+            Err(CpuReferenceError::Invalid(errors)) => {
+                for e in errors {
+                    eprintln!("invalid octree: {e}");
+                }
+
+                std::process::exit(1);
+            },
+            Err(CpuReferenceError::Io(e)) => {
+                eprintln!("failed to write {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    let application = app::Application::init(config);
 
-    application.run();
+    application.run(world);
 }